@@ -1,8 +1,79 @@
+use crate::interpret::Function;
 use crate::lex::Token;
+use crate::shell::{Output, Quotes};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
-pub enum Stmt {
+/// Byte range of a node in the source it was parsed from.
+pub type Span = std::ops::Range<usize>;
+
+// Spans are left out when comparing nodes, two nodes are equal when they
+// have the same shape wherever they were written.
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl PartialEq for Stmt {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// Where the local a variable or an assignment refers to lives, filled in
+    /// by the resolver. Globals are looked up by name and have none.
+    pub slot: Cell<Option<Slot>>,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            slot: Cell::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Expr")
+            .field("kind", &self.kind)
+            .field("span", &self.span)
+            .finish()
+    }
+}
+
+/// A local binding, `depth` scopes out from the innermost one at `index` in
+/// that scope's bindings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
     // Declarations
     VariableDeclaration {
         name: String,
@@ -12,10 +83,16 @@ pub enum Stmt {
         name: String,
         value: Expr,
     },
+    CommentDeclaration {
+        name: String,
+        value: Expr,
+    },
     FnDeclaration {
         name: String,
         params: Vec<String>,
         body: Box<Stmt>,
+        // `StmtKind::Comment`s written directly above the declaration
+        comments: Vec<Stmt>,
     },
 
     // Not Declaration
@@ -28,13 +105,27 @@ pub enum Stmt {
         label: Option<String>,
         body: Box<Stmt>,
     },
+    For {
+        name: String, // binding for each item
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
     Block(Vec<Stmt>),
+    Return(Option<Expr>),
+    Break(Option<String>),    // label
+    Continue(Option<String>), // label
+    Throw(Expr),
+    Try {
+        body: Box<Stmt>,
+        name: Option<String>, // binding for the caught value
+        handler: Box<Stmt>,
+    },
     Comment(String),
     Expr(Expr),
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Expr {
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
     Binary {
         left: Box<Expr>,
         op: Token,
@@ -49,14 +140,61 @@ pub enum Expr {
 
     Variable(String),
     Assignment(String, Box<Expr>),
+
+    List(Vec<Expr>),
+    Map(Vec<(Expr, Expr)>), // keys and values
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    IndexAssignment {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: String,
+    },
+    // the literal parts and embedded expressions of a string with `${...}`
+    Interpolation(Vec<Expr>),
+    // an anonymous `fn (params) { body }`
+    Function {
+        params: Vec<String>,
+        body: Box<Stmt>,
+    },
+}
+
+/// A piece of the command of a comment, see `parse::command`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Text(String),
+    /// `$`, the value the comment is run for, like what a function returned.
+    Dollar(Quotes, Span),
+    /// `${expr}`
+    Expr(Expr, Quotes),
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum Value {
     Null,
     Bool(bool),
     Num(f64),
     Str(String),
+    // comment text without the leading `#`, run when its value is needed
+    Comment(String),
+    Function(Rc<Function>),
+    // collections are shared, changing one through any of its copies
+    // changes them all
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<BTreeMap<String, Value>>>),
+    // the result of running a command comment
+    Output(Rc<Output>),
 }
 
 impl fmt::Debug for Value {
@@ -65,7 +203,82 @@ impl fmt::Debug for Value {
             Self::Bool(value) => write!(f, "{}", value),
             Self::Num(value) => write!(f, "{}", value),
             Self::Str(value) => write!(f, "{}", value),
+            Self::Comment(value) => write!(f, "# {}", value),
+            Self::Output(output) => write!(f, "{}", output.stdout),
             Self::Null => write!(f, "null"),
+            Self::Function(function) => match &function.name {
+                Some(name) => write!(f, "<fn {}({})>", name, function.params.join(", ")),
+                None => write!(f, "<fn({})>", function.params.join(", ")),
+            },
+            Self::List(items) => {
+                f.write_str("[")?;
+                for (index, item) in items.borrow().iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    item.fmt_item(f)?;
+                }
+                f.write_str("]")
+            }
+            Self::Map(entries) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in entries.borrow().iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{:?}: ", key)?;
+                    value.fmt_item(f)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl Value {
+    /// `null` and `false` are falsy, every other value is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Null | Self::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool(_) => "bool",
+            Self::Num(_) => "number",
+            Self::Str(_) => "string",
+            Self::Comment(_) => "comment",
+            Self::Function(_) => "function",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Output(_) => "output",
+        }
+    }
+
+    pub fn list(items: Vec<Value>) -> Self {
+        Self::List(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(entries: BTreeMap<String, Value>) -> Self {
+        Self::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    // strings are quoted inside collections, so `["a, b"]` isn't `[a, b]`
+    fn fmt_item(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Str(value) => write!(f, "{:?}", value),
+            value => write!(f, "{:?}", value),
         }
     }
 }
+
+/// The command of a `# > command` comment, `None` for plain comments.
+/// `text` is the comment without its leading `#`, as stored in `StmtKind::Comment`.
+pub fn shell_command(text: &str) -> Option<&str> {
+    text.strip_prefix('>').map(str::trim)
+}
+
+/// The command of a `# ! command` comment, which runs when a function throws.
+pub fn failure_command(text: &str) -> Option<&str> {
+    text.strip_prefix('!').map(str::trim)
+}
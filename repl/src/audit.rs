@@ -0,0 +1,191 @@
+use crate::ast::Span;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A file every command run is appended to, one JSON object per line:
+///
+/// ```json
+/// {"time":"2021-08-01T12:00:00.000Z","script":"deploy.repl","span":[10,32],"command":"git push","status":0,"duration_ms":840}
+/// ```
+///
+/// `span` is where the command's comment is in the script, `null` when it
+/// isn't known. `status` is `null` when the command was killed, along with
+/// an `error` when it couldn't be run or ran out of time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditLog {
+    path: PathBuf,
+    // the script the commands come from
+    script: Option<String>,
+}
+
+/// A command that was run.
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub command: &'a str,
+    pub span: Option<&'a Span>,
+    pub started: SystemTime,
+    pub duration: Duration,
+    pub status: Option<i32>,
+    pub error: Option<String>,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            script: None,
+        }
+    }
+
+    /// Notes that the commands come from `script`.
+    pub fn script(self, script: &Path) -> Self {
+        Self {
+            script: Some(script.display().to_string()),
+            ..self
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a record to the log, creating it if needed.
+    pub fn record(&self, record: &Record) -> io::Result<()> {
+        let null = || "null".to_string();
+        let mut fields = vec![
+            ("time", string(&timestamp(record.started))),
+            ("script", self.script.as_deref().map_or_else(null, string)),
+            (
+                "span",
+                record
+                    .span
+                    .map_or_else(null, |span| format!("[{},{}]", span.start, span.end)),
+            ),
+            ("command", string(record.command)),
+            (
+                "status",
+                record.status.map_or_else(null, |status| status.to_string()),
+            ),
+            ("duration_ms", record.duration.as_millis().to_string()),
+        ];
+        if let Some(error) = &record.error {
+            fields.push(("error", string(error)));
+        }
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .collect();
+        let line = format!("{{{}}}\n", fields.join(","));
+
+        // one write per record, so records of runs sharing a log don't mix
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())
+    }
+}
+
+// a JSON string
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// `time` in UTC, like `2021-08-01T12:00:00.000Z`
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // days since 1970-01-01 to a date, from Howard Hinnant's `civil_from_days`
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn timestamps() {
+        let time = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(timestamp(time(951_782_400)), "2000-02-29T00:00:00.000Z");
+        assert_eq!(
+            timestamp(time(1_627_819_199) + Duration::from_millis(5)),
+            "2021-08-01T11:59:59.005Z"
+        );
+    }
+
+    #[test]
+    fn records() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("audit");
+        let path = dir.join("audit.jsonl");
+        let log = AuditLog::new(&path).script(Path::new("deploy.repl"));
+        let mut record = Record {
+            command: "echo \"hi\"\tthere",
+            span: Some(&(3..20)),
+            started: UNIX_EPOCH,
+            duration: Duration::from_millis(1500),
+            status: Some(0),
+            error: None,
+        };
+        log.record(&record)?;
+        record.span = None;
+        record.status = None;
+        record.error = Some("it ran for longer than 1s".to_string());
+        log.record(&record)?;
+
+        let lines = std::fs::read_to_string(&path)?;
+        assert_eq!(
+            lines,
+            "{\"time\":\"1970-01-01T00:00:00.000Z\",\"script\":\"deploy.repl\",\"span\":[3,20],\
+             \"command\":\"echo \\\"hi\\\"\\tthere\",\"status\":0,\"duration_ms\":1500}\n\
+             {\"time\":\"1970-01-01T00:00:00.000Z\",\"script\":\"deploy.repl\",\"span\":null,\
+             \"command\":\"echo \\\"hi\\\"\\tthere\",\"status\":null,\"duration_ms\":1500,\
+             \"error\":\"it ran for longer than 1s\"}\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use crate::ast::Span;
+use std::fmt;
+
+/// 1-based line and column of the byte `offset` in `source`.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = floor_char_boundary(source, offset);
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Renders `message` with the line of `source` that `span` starts on,
+/// underlining the span with carets:
+///
+/// ```text
+/// error: expected `)`, found `}`
+///  --> 1:13
+///   |
+/// 1 | let x = f(1 }
+///   |             ^
+/// ```
+pub fn render(source: &str, span: &Span, message: impl fmt::Display) -> String {
+    snippet("error", source, span, message)
+}
+
+/// Like `render`, for a note that explains an error rendered before it.
+pub fn note(source: &str, span: &Span, message: impl fmt::Display) -> String {
+    snippet("note", source, span, message)
+}
+
+fn snippet(label: &str, source: &str, span: &Span, message: impl fmt::Display) -> String {
+    let start = floor_char_boundary(source, span.start);
+    let end = floor_char_boundary(source, span.end.max(start));
+    let (line, column) = line_col(source, start);
+
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |index| start + index);
+    let text = &source[line_start..line_end];
+
+    // keep tabs so the carets line up with the text above them
+    let padding: String = source[line_start..start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let carets = source[start..end.min(line_end)].chars().count().max(1);
+
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{label}: {message}\n\
+         {gutter}--> {line}:{column}\n\
+         {gutter} |\n\
+         {line} | {text}\n\
+         {gutter} | {padding}{carets}",
+        label = label,
+        message = message,
+        gutter = gutter,
+        line = line,
+        column = column,
+        text = text,
+        padding = padding,
+        carets = "^".repeat(carets),
+    )
+}
+
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_underline() {
+        let source = "let x = 1\nlet y = foo + 2\n";
+        assert_eq!(line_col(source, 18), (2, 9));
+        assert_eq!(
+            render(source, &(18..21), "undefined variable `foo`"),
+            "error: undefined variable `foo`\n \
+             --> 2:9\n  \
+             |\n\
+             2 | let y = foo + 2\n  \
+             |         ^^^"
+        );
+    }
+
+    #[test]
+    fn end_of_input() {
+        let source = "\tf(1";
+        assert_eq!(
+            render(source, &(4..4), "expected `)`, found end of input"),
+            "error: expected `)`, found end of input\n \
+             --> 1:5\n  \
+             |\n\
+             1 | \tf(1\n  \
+             | \t   ^"
+        );
+    }
+}
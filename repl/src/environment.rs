@@ -0,0 +1,178 @@
+use crate::ast::{Slot, Span, Value};
+use crate::interpret::ErrorKind;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A variable or a constant, along with where it was declared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub value: Value,
+    pub constant: bool,
+    pub declared: Span,
+}
+
+/// The bindings of one scope. Names that aren't found in it are looked up
+/// in the scopes enclosing it, innermost first.
+///
+/// Bindings are kept in the order they're declared, so the resolver can
+/// tell where a local will be before it exists.
+#[derive(Debug, Default)]
+pub struct Environment {
+    slots: Vec<Binding>,
+    // the latest slot of every name, a redeclared name keeps its old one
+    names: HashMap<String, usize>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            enclosing: Some(enclosing),
+            ..Self::default()
+        }
+    }
+
+    /// Binds `name` in this scope, shadowing any binding of the same name.
+    pub fn define(&mut self, name: &str, binding: Binding) {
+        self.names.insert(name.to_string(), self.slots.len());
+        self.slots.push(binding);
+    }
+
+    /// The binding of `name` in this scope alone.
+    pub fn binding(&self, name: &str) -> Option<&Binding> {
+        self.names.get(name).map(|&index| &self.slots[index])
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match (self.binding(name), &self.enclosing) {
+            (Some(binding), _) => Some(binding.value.clone()),
+            (None, Some(enclosing)) => enclosing.borrow().get(name),
+            (None, None) => None,
+        }
+    }
+
+    pub fn get_at(&self, slot: Slot) -> Option<Value> {
+        match (slot.depth, &self.enclosing) {
+            (0, _) => self
+                .slots
+                .get(slot.index)
+                .map(|binding| binding.value.clone()),
+            (depth, Some(enclosing)) => enclosing.borrow().get_at(Slot {
+                depth: depth - 1,
+                ..slot
+            }),
+            (_, None) => None,
+        }
+    }
+
+    /// Changes the value of the closest binding of `name`, unless it's a
+    /// constant.
+    pub fn assign(&mut self, name: &str, value: Value) -> Result<(), ErrorKind> {
+        match (self.names.get(name), &self.enclosing) {
+            (Some(&index), _) => assign(&mut self.slots[index], name, value),
+            (None, Some(enclosing)) => enclosing.borrow_mut().assign(name, value),
+            (None, None) => Err(ErrorKind::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    pub fn assign_at(&mut self, slot: Slot, name: &str, value: Value) -> Result<(), ErrorKind> {
+        match (slot.depth, &self.enclosing) {
+            (0, _) => match self.slots.get_mut(slot.index) {
+                Some(binding) => assign(binding, name, value),
+                None => Err(ErrorKind::UndefinedVariable(name.to_string())),
+            },
+            (depth, Some(enclosing)) => enclosing.borrow_mut().assign_at(
+                Slot {
+                    depth: depth - 1,
+                    ..slot
+                },
+                name,
+                value,
+            ),
+            (_, None) => Err(ErrorKind::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// The scope this one is nested in, `None` for the global scope.
+    pub fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    /// Every binding of this scope in the order they were declared, shadowed
+    /// ones included.
+    pub fn slots(&self) -> &[Binding] {
+        &self.slots
+    }
+
+    /// The slot each name of this scope refers to.
+    pub fn names(&self) -> &HashMap<String, usize> {
+        &self.names
+    }
+
+    /// The bindings of this scope alone, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Binding)> {
+        let mut bindings: Vec<_> = self
+            .names
+            .iter()
+            .map(|(name, &index)| (name.clone(), self.slots[index].clone()))
+            .collect();
+        bindings.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        bindings
+    }
+}
+
+fn assign(binding: &mut Binding, name: &str, value: Value) -> Result<(), ErrorKind> {
+    if binding.constant {
+        return Err(ErrorKind::AssignToConstant(
+            name.to_string(),
+            binding.declared.clone(),
+        ));
+    }
+    binding.value = value;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(value: f64, constant: bool) -> Binding {
+        Binding {
+            value: Value::Num(value),
+            constant,
+            declared: 0..0,
+        }
+    }
+
+    #[test]
+    fn scopes() {
+        let globals = Rc::new(RefCell::new(Environment::default()));
+        globals.borrow_mut().define("a", binding(1f64, false));
+        globals.borrow_mut().define("b", binding(2f64, true));
+
+        let mut block = Environment::new(globals.clone());
+        block.define("a", binding(3f64, false));
+        assert_eq!(block.get("a"), Some(Value::Num(3f64)));
+        assert_eq!(block.get("b"), Some(Value::Num(2f64)));
+        assert_eq!(block.get("c"), None);
+
+        // assigning reaches the closest binding only
+        block.assign("a", Value::Null).unwrap();
+        assert_eq!(globals.borrow().get("a"), Some(Value::Num(1f64)));
+
+        assert!(matches!(
+            block.assign("b", Value::Null),
+            Err(ErrorKind::AssignToConstant(name, _)) if name == "b"
+        ));
+        assert!(matches!(
+            block.assign("c", Value::Null),
+            Err(ErrorKind::UndefinedVariable(_))
+        ));
+
+        let outer = Slot { depth: 1, index: 0 };
+        assert_eq!(block.get_at(outer), Some(Value::Num(1f64)));
+        block.assign_at(outer, "a", Value::Bool(true)).unwrap();
+        assert_eq!(globals.borrow().get("a"), Some(Value::Bool(true)));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::ast::{self, Span, Stmt, StmtKind, Value};
+use crate::interpret::Interpreter;
+use crate::lex::{Lexer, Token, KEYWORDS};
+use crate::parse::{Parser, ParserError};
+use crate::stdlib;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::Context;
+use rustyline_derive::Helper;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+// ANSI styles of the highlighter
+const RESET: &str = "\x1b[0m";
+const KEYWORD: &str = "\x1b[1;35m";
+const STRING: &str = "\x1b[32m";
+const LITERAL: &str = "\x1b[33m";
+const IDENT: &str = "\x1b[36m";
+const OPERATOR: &str = "\x1b[1m";
+const ERROR: &str = "\x1b[4;31m";
+const COMMENT: &str = "\x1b[2m";
+const SHELL_COMMENT: &str = "\x1b[1;33m";
+const FAILURE_COMMENT: &str = "\x1b[1;31m";
+const MATCHING_BRACKET: &str = "\x1b[1;34m";
+
+/// The line editor helper of the REPL.
+#[derive(Helper, Default)]
+pub struct ReplHelper {
+    files: FilenameCompleter,
+    // what the session has defined, as of the last `refresh`
+    bindings: Vec<String>,
+    params: HashMap<String, Vec<String>>,
+}
+
+impl ReplHelper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Picks up the names defined by the input the interpreter just ran.
+    pub fn refresh(&mut self, interpreter: &Interpreter) {
+        self.bindings.clear();
+        self.params.clear();
+        for (name, binding) in interpreter.globals() {
+            match binding.value {
+                Value::Function(function) => {
+                    self.params.insert(name, function.params.clone());
+                }
+                _ => self.bindings.push(name),
+            }
+        }
+    }
+
+    // keywords, bindings, functions and builtins starting with `word`
+    fn candidates(&self, word: &str) -> Vec<String> {
+        let names = KEYWORDS
+            .iter()
+            .copied()
+            .chain(self.bindings.iter().map(String::as_str));
+        let functions = self
+            .params
+            .keys()
+            .map(String::as_str)
+            .chain(stdlib::BUILTINS.iter().map(|builtin| builtin.name));
+
+        let mut candidates: Vec<String> = names
+            .map(str::to_string)
+            .chain(functions.map(|name| format!("{}(", name)))
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    // the parameters of the function being called right before the cursor
+    fn params_hint(&self, before: &str) -> Option<String> {
+        let callee = before.strip_suffix('(')?;
+        let start = word_start(callee);
+        let params = self.params.get(&callee[start..])?;
+        Some(format!("{})", params.join(", ")))
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // the rest of a `# >` comment is a shell command
+        if in_command(&line[..pos]) {
+            return self.files.complete(line, pos, ctx);
+        }
+
+        let start = word_start(&line[..pos]);
+        if start == pos {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = self
+            .candidates(&line[start..pos])
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+/// The parameters of a function, shown after its name and `(`.
+pub struct ParamsHint(String);
+
+impl Hint for ParamsHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    // the names are only a reminder, not something to insert
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = ParamsHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ParamsHint> {
+        if pos < line.len() {
+            return None;
+        }
+        self.params_hint(line).map(ParamsHint)
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        // meta-commands fit on a line
+        if ctx.input().trim_start().starts_with(':') {
+            return Ok(ValidationResult::Valid(None));
+        }
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if line.is_empty() {
+            return Cow::Borrowed(line);
+        }
+        Cow::Owned(highlight(line, pos))
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", COMMENT, hint, RESET))
+    }
+
+    // the bracket matching the cursor moves along with it
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+// where the identifier ending at the end of `text` starts
+fn word_start(text: &str) -> usize {
+    text.char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+        .last()
+        .map_or(text.len(), |(index, _)| index)
+}
+
+// whether `text` ends inside an executable comment
+fn in_command(text: &str) -> bool {
+    match tokens(text).last() {
+        Some((Token::Comment, span)) if span.end == text.len() => {
+            let comment = text[span.start + 1..].trim();
+            ast::shell_command(comment).is_some() || ast::failure_command(comment).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// Colours `line` by token, and the bracket matching the one at `pos`.
+/// Executable comments stand out from plain ones.
+pub fn highlight(line: &str, pos: usize) -> String {
+    let tokens = tokens(line);
+    let matching = matching_bracket(&tokens, pos);
+
+    let mut highlighted = String::with_capacity(line.len() * 2);
+    let mut end = 0;
+    for (index, (token, span)) in tokens.iter().enumerate() {
+        highlighted.push_str(&line[end..span.start]);
+        let text = &line[span.clone()];
+        let style = if matching == Some(index) {
+            Some(MATCHING_BRACKET)
+        } else {
+            style(token, text)
+        };
+        match style {
+            Some(style) => {
+                highlighted.push_str(style);
+                highlighted.push_str(text);
+                highlighted.push_str(RESET);
+            }
+            None => highlighted.push_str(text),
+        }
+        end = span.end;
+    }
+    highlighted.push_str(&line[end..]);
+    highlighted
+}
+
+fn tokens(line: &str) -> Vec<(Token, Span)> {
+    let mut lexer = Lexer::new(line);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next() {
+        tokens.push((token, lexer.span()));
+    }
+    tokens
+}
+
+fn style(token: &Token, text: &str) -> Option<&'static str> {
+    use Token::*;
+    match token {
+        Let | Const | Func | CommentKeyword | Loop | For | In | Break | Continue | Return | If
+        | Else | Throw | Try | Catch => Some(KEYWORD),
+        Null | True | False | Num => Some(LITERAL),
+        Str => Some(STRING),
+        Ident => Some(IDENT),
+        Minus | Plus | Slash | Star | Bang | BangEqual | Equal | EqualEqual | Greater
+        | GreaterEqual | Less | LessEqual | And | Or => Some(OPERATOR),
+        Comment => {
+            let text = text[1..].trim();
+            if ast::shell_command(text).is_some() {
+                Some(SHELL_COMMENT)
+            } else if ast::failure_command(text).is_some() {
+                Some(FAILURE_COMMENT)
+            } else {
+                Some(COMMENT)
+            }
+        }
+        Error => Some(ERROR),
+        LeftParen | RightParen | LeftBrace | RightBrace | LeftBracket | RightBracket | Comma
+        | SemiColon | Colon | Dot => None,
+    }
+}
+
+// index of the token matching the bracket under or right before the cursor
+fn matching_bracket(tokens: &[(Token, Span)], pos: usize) -> Option<usize> {
+    let is_bracket = |token: &Token| {
+        matches!(
+            token,
+            Token::LeftParen
+                | Token::RightParen
+                | Token::LeftBrace
+                | Token::RightBrace
+                | Token::LeftBracket
+                | Token::RightBracket
+        )
+    };
+    let index = tokens
+        .iter()
+        .position(|(token, span)| span.start == pos && is_bracket(token))
+        .or_else(|| {
+            tokens
+                .iter()
+                .position(|(token, span)| span.end == pos && is_bracket(token))
+        })?;
+
+    let (open, close, forward) = match tokens[index].0 {
+        Token::LeftParen => (Token::LeftParen, Token::RightParen, true),
+        Token::RightParen => (Token::RightParen, Token::LeftParen, false),
+        Token::LeftBrace => (Token::LeftBrace, Token::RightBrace, true),
+        Token::RightBrace => (Token::RightBrace, Token::LeftBrace, false),
+        Token::LeftBracket => (Token::LeftBracket, Token::RightBracket, true),
+        _ => (Token::RightBracket, Token::LeftBracket, false),
+    };
+    let mut depth = 0;
+    let mut check = |candidate: usize| {
+        let token = &tokens[candidate].0;
+        if *token == open {
+            depth += 1;
+        } else if *token == close {
+            depth -= 1;
+        }
+        depth == 0
+    };
+    if forward {
+        (index..tokens.len()).find(|&candidate| check(candidate))
+    } else {
+        (0..=index).rev().find(|&candidate| check(candidate))
+    }
+}
+
+/// Whether more lines are needed to finish `input`: it runs out in the
+/// middle of a statement or a string, or it ends with an `if` that an `else`
+/// on the next line could still continue. A blank line submits the latter.
+pub fn is_incomplete(input: &str) -> bool {
+    if in_string(input) {
+        return true;
+    }
+
+    match Parser::new(input).parse() {
+        // later errors may only come from the parser giving up on the first
+        Err(errors) => matches!(
+            errors.errors.first(),
+            Some(ParserError::UnexpectedEof(..) | ParserError::ExpectedExpression(_))
+        ),
+        Ok(statements) => {
+            let last_line = input.rsplit('\n').next().unwrap_or_default();
+            !last_line.trim().is_empty()
+                && matches!(
+                    statements.last(),
+                    Some(Stmt {
+                        kind: StmtKind::If {
+                            otherwise: None,
+                            ..
+                        },
+                        ..
+                    })
+                )
+        }
+    }
+}
+
+// whether a string is still open at the end of `input`
+fn in_string(input: &str) -> bool {
+    let mut chars = input.chars();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_string = !in_string,
+            '\\' if in_string => {
+                chars.next();
+            }
+            // quotes in comments don't count
+            '#' if !in_string => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            _ => {}
+        }
+    }
+    in_string
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_styles(text: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                stripped.push(c);
+            }
+        }
+        stripped
+    }
+
+    #[test]
+    fn highlighting() {
+        let line = "let x = f(\"a\", 1) # > ls";
+        let highlighted = highlight(line, 0);
+        assert_eq!(strip_styles(&highlighted), line);
+        assert!(highlighted.starts_with("\x1b[1;35mlet\x1b[0m \x1b[36mx\x1b[0m"));
+        assert!(highlighted.contains("\x1b[32m\"a\"\x1b[0m"));
+        assert!(highlighted.ends_with("\x1b[1;33m# > ls\x1b[0m"));
+
+        assert_eq!(highlight("# ! cowsay", 0), "\x1b[1;31m# ! cowsay\x1b[0m");
+        assert_eq!(highlight("# note", 0), "\x1b[2m# note\x1b[0m");
+        assert!(highlight("let x = 1 ~", 0).ends_with("\x1b[4;31m~\x1b[0m"));
+    }
+
+    #[test]
+    fn matching_brackets() {
+        let brackets = tokens("f(g(1), { })");
+        // cursor on the outer `(`, then right after the outer `)`
+        assert_eq!(matching_bracket(&brackets, 1), Some(brackets.len() - 1));
+        assert_eq!(matching_bracket(&brackets, 12), Some(1));
+        assert_eq!(matching_bracket(&brackets, 8), Some(8));
+        assert_eq!(matching_bracket(&brackets, 0), None);
+        assert_eq!(matching_bracket(&tokens("f(1"), 1), None);
+    }
+
+    #[test]
+    fn completion() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::new();
+        let program = "let count = 1\nconst cap = 2\nfn add(a, b) { a + b }";
+        interpreter.interpret(&Parser::new(program).parse()?)?;
+
+        let mut helper = ReplHelper::new();
+        helper.refresh(&interpreter);
+
+        assert_eq!(
+            helper.candidates("co"),
+            ["comment", "const", "continue", "count"]
+        );
+        assert_eq!(helper.candidates("ca"), ["cap", "catch"]);
+        assert_eq!(helper.candidates("a"), ["add(", "arg(", "arg_count("]);
+        assert_eq!(helper.candidates("pri"), ["print("]);
+
+        assert_eq!(
+            helper.params_hint("let x = add("),
+            Some("a, b)".to_string())
+        );
+        assert_eq!(helper.params_hint("add"), None);
+        assert_eq!(helper.params_hint("print("), None);
+        Ok(())
+    }
+
+    #[test]
+    fn shell_commands() {
+        assert!(in_command("# > cat Cargo"));
+        assert!(in_command("let x = 1 # ! rm "));
+        assert!(!in_command("# note"));
+        assert!(!in_command("# > ls\nfoo"));
+        assert_eq!(word_start("let x = fo"), 8);
+        assert_eq!(word_start("f("), 2);
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("if 5 < 3"));
+        assert!(is_incomplete("if 5 < 3 {\n    # > sudo shutdown"));
+        assert!(is_incomplete("if 5 < 3 {\n}\nelse"));
+        assert!(is_incomplete("fn add(a, b) {\n  a +"));
+        assert!(is_incomplete("let s = \"hello\n"));
+        assert!(is_incomplete("let s = \"a \\\" b"));
+
+        assert!(!is_incomplete(""));
+        assert!(!is_incomplete("let x = 1"));
+        assert!(!is_incomplete("let s = \"# not a comment\""));
+        assert!(!is_incomplete("# \"quoted\" in a comment"));
+        // real errors are submitted to be reported
+        assert!(!is_incomplete("let x = )"));
+        assert!(!is_incomplete("fn f() {\n  let = 1"));
+    }
+
+    #[test]
+    fn else_continuation() {
+        assert!(is_incomplete("if x {}"));
+        assert!(!is_incomplete("if x {}\n"));
+        assert!(!is_incomplete("if x {}\nelse {}"));
+        assert!(!is_incomplete("if x {} let y = 1"));
+    }
+}
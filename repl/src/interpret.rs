@@ -0,0 +1,1734 @@
+use crate::ast::{self, Expr, ExprKind, Part, Slot, Span, Stmt, StmtKind, Value};
+use crate::audit::{AuditLog, Record};
+use crate::environment::{Binding, Environment};
+use crate::lex::Token;
+use crate::parse::{self, ParserError};
+use crate::policy::{Denial, Policy};
+use crate::resolve::{self, ResolveError};
+use crate::shell::{self, Output, Session};
+use crate::stdlib;
+use crate::strip::Stripper;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime};
+use thiserror::Error;
+
+/// An error raised while running a program, along with the span of the
+/// innermost node that was running when it happened.
+#[derive(Error, Debug)]
+#[error("{kind}")]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+}
+
+impl RuntimeError {
+    // errors keep the span of the node they were raised in
+    fn at(mut self, span: &Span) -> Self {
+        self.span.get_or_insert_with(|| span.clone());
+        self
+    }
+}
+
+impl From<ErrorKind> for RuntimeError {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, span: None }
+    }
+}
+
+impl From<Vec<ResolveError>> for RuntimeError {
+    // points at the first problem, the others have their own spans
+    fn from(errors: Vec<ResolveError>) -> Self {
+        Self {
+            span: errors.first().map(|error| error.span().clone()),
+            kind: ErrorKind::Resolve(errors),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ErrorKind {
+    #[error("undefined variable `{0}`")]
+    UndefinedVariable(String),
+
+    #[error("invalid operand for {0:?}: `{1:?}`")]
+    InvalidOperand(Token, Value),
+
+    #[error("invalid operands for {0:?}: `{1:?}` and `{2:?}`")]
+    InvalidOperands(Token, Value, Value),
+
+    #[error("failed to run `{0}`: {1}")]
+    Shell(String, std::io::Error),
+
+    #[error("`{0}` was not run, {1}")]
+    Denied(String, Denial),
+
+    #[error("failed to write to the audit log {}: {1}", .0.path().display())]
+    Audit(AuditLog, std::io::Error),
+
+    #[error("cannot assign to `{0}`, it is a constant")]
+    AssignToConstant(String, Span), // name, where it was declared
+
+    #[error("undefined function `{0}`")]
+    UndefinedFunction(String),
+
+    #[error("`{0:?}` is not a comment")]
+    NotAComment(Value),
+
+    #[error("`{0:?}` is not a function")]
+    NotCallable(Value),
+
+    #[error("`{0}` expects {1} arguments but got {2}")]
+    Arity(String, usize, usize),
+
+    #[error("invalid arguments for `{0}`: {1:?}")]
+    InvalidArguments(String, Vec<Value>),
+
+    #[error("`{0:?}` has no property `{1}`")]
+    UndefinedProperty(Value, String),
+
+    #[error("map keys have to be strings, got `{0:?}`")]
+    InvalidKey(Value),
+
+    #[error("`{0:?}` cannot be indexed with `{1:?}`")]
+    InvalidIndex(Value, Value),
+
+    #[error("index {0} is out of range for a list of {1} items")]
+    IndexOutOfRange(usize, usize),
+
+    #[error("undefined key `{0}`")]
+    UndefinedKey(String),
+
+    #[error("`{0:?}` cannot be iterated over")]
+    NotIterable(Value),
+
+    #[error("`$` has no value here, write `$$` for a `$`")]
+    UnboundDollar,
+
+    #[error("{0}")]
+    Parse(ParserError),
+
+    #[error("in `{0}`: {1}")]
+    Substitution(String, String), // command, what went wrong
+
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Resolve(Vec<ResolveError>),
+
+    #[error("uncaught exception: {0}")]
+    Thrown(Exception),
+}
+
+impl RuntimeError {
+    // what a `catch` binding or a `# !` comment's `$` sees
+    fn to_value(&self) -> Value {
+        match &self.kind {
+            ErrorKind::Thrown(exception) => exception.value.clone(),
+            error => Value::Str(error.to_string()),
+        }
+    }
+}
+
+/// A value raised by `throw`, along with the functions it was thrown
+/// through, innermost first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exception {
+    pub value: Value,
+    pub stack: Vec<String>,
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.value)?;
+        for name in &self.stack {
+            write!(f, "\n    in {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+// Ways for a statement to stop before reaching its end
+enum Unwind {
+    Error(RuntimeError),
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Value),
+}
+
+impl Unwind {
+    fn at(self, span: &Span) -> Self {
+        match self {
+            Self::Error(error) => Self::Error(error.at(span)),
+            unwind => unwind,
+        }
+    }
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Self::Error(error)
+    }
+}
+
+impl From<ErrorKind> for Unwind {
+    fn from(kind: ErrorKind) -> Self {
+        Self::Error(kind.into())
+    }
+}
+
+/// A function along with the scope it was created in, which its body keeps
+/// seeing after that scope has ended.
+pub struct Function {
+    /// `None` for anonymous functions.
+    pub name: Option<String>,
+    pub params: Vec<String>,
+    pub body: Stmt,
+    /// `StmtKind::Comment`s, run as hooks when the function returns or throws.
+    pub comments: Vec<Stmt>,
+    pub span: Span,
+    closure: Rc<RefCell<Environment>>,
+}
+
+impl Function {
+    // how the function is called in errors and stack traces
+    fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<anonymous>")
+    }
+}
+
+// two functions are equal only if they are the same function
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Never run executable comments, and strip every statement that uses a
+    /// value derived from one before running a program.
+    pub without_comments: bool,
+    /// Arguments passed to the script, read with `arg(index)`.
+    pub args: Vec<String>,
+    /// What executable comments are allowed to run.
+    pub policy: Policy,
+    /// Print the commands of executable comments instead of running them.
+    /// They give an empty output and succeed.
+    pub dry_run: bool,
+    /// Where every command run is recorded.
+    pub audit: Option<AuditLog>,
+    /// Run every command in one shell kept for the whole run, so a `cd` or
+    /// an `export` carries over to the commands after it.
+    pub shell_session: bool,
+}
+
+pub struct Interpreter {
+    options: Options,
+    stripper: Stripper,
+    globals: Rc<RefCell<Environment>>,
+    // the innermost scope of the code being run
+    environment: Rc<RefCell<Environment>>,
+    // names of the functions currently being called, innermost last
+    calls: Vec<String>,
+    // the shell commands run in with `shell_session`, started by the first
+    session: Option<Session>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::default()));
+        Self {
+            options: Options::default(),
+            stripper: Stripper::new(),
+            environment: globals.clone(),
+            globals,
+            calls: Vec::new(),
+            session: None,
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// The variables and constants of the global scope, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Binding)> {
+        self.globals.borrow().bindings()
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.options.args
+    }
+
+    /// Ends the shell of `shell_session`, so the next command starts a new
+    /// one.
+    pub fn restart_shell(&mut self) {
+        self.session = None;
+    }
+
+    /// The directory commands run in: the shell's with `shell_session`, and
+    /// ours otherwise.
+    pub fn shell_cwd(&mut self) -> io::Result<PathBuf> {
+        if !self.options.shell_session {
+            return std::env::current_dir();
+        }
+        self.session()?.cwd().map(PathBuf::from)
+    }
+
+    fn session(&mut self) -> io::Result<&mut Session> {
+        match &mut self.session {
+            Some(session) => Ok(session),
+            session => Ok(session.insert(Session::start(&self.options.policy)?)),
+        }
+    }
+
+    /// Runs the statements in order and returns the value of the last one.
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<Value, RuntimeError> {
+        let stripped;
+        let statements = if self.options.without_comments {
+            stripped = self.stripper.strip(statements.to_vec());
+            &stripped
+        } else {
+            statements
+        };
+
+        resolve::resolve(statements, &self.globals.borrow())?;
+        let result = self.execute_all(statements);
+
+        match result {
+            Ok(value) => Ok(value),
+            Err(Unwind::Error(error)) => Err(error),
+            Err(_) => unreachable!("jumps out of functions and loops are rejected by the resolver"),
+        }
+    }
+
+    fn execute_all(&mut self, statements: &[Stmt]) -> Result<Value, Unwind> {
+        let mut value = Value::Null;
+        for statement in statements {
+            value = self.execute(statement)?;
+        }
+        Ok(value)
+    }
+
+    /// Calls the global `name`, or the builtin of that name.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let callee = self.globals.borrow().get(name);
+        match (callee, stdlib::lookup(name)) {
+            (Some(callee), _) => self.call_value(callee, args),
+            (None, Some(builtin)) => builtin.call(self, args),
+            (None, None) => Err(ErrorKind::UndefinedFunction(name.to_string()).into()),
+        }
+    }
+
+    /// Calls a value, which has to be a function.
+    pub fn call_value(&mut self, callee: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match callee {
+            Value::Function(function) => self.call_function(&function, args),
+            value => Err(ErrorKind::NotCallable(value).into()),
+        }
+    }
+
+    // A function's `# >` comments run once it returns and its `# !` comments
+    // run if it fails, with `$` standing for the returned value or the error.
+    fn call_function(
+        &mut self,
+        function: &Function,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        if args.len() != function.params.len() {
+            return Err(ErrorKind::Arity(
+                function.display_name().to_string(),
+                function.params.len(),
+                args.len(),
+            )
+            .into());
+        }
+
+        let mut locals = Environment::new(function.closure.clone());
+        for (param, value) in function.params.iter().zip(args) {
+            let binding = Binding {
+                value,
+                constant: false,
+                declared: function.span.clone(),
+            };
+            locals.define(param, binding);
+        }
+
+        self.calls.push(function.display_name().to_string());
+        let result = match self.execute_in(std::slice::from_ref(&function.body), locals) {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(error)) => Err(error),
+            Err(_) => unreachable!("jumps out of functions are rejected by the resolver"),
+        };
+        self.calls.pop();
+
+        match result {
+            Ok(value) => {
+                self.run_hooks(&function.comments, ast::shell_command, &value)?;
+                Ok(value)
+            }
+            Err(error) => {
+                self.run_hooks(&function.comments, ast::failure_command, &error.to_value())?;
+                Err(error)
+            }
+        }
+    }
+
+    fn run_hooks(
+        &mut self,
+        comments: &[Stmt],
+        command: fn(&str) -> Option<&str>,
+        dollar: &Value,
+    ) -> Result<(), RuntimeError> {
+        if self.options.without_comments {
+            return Ok(());
+        }
+
+        for comment in comments {
+            if let StmtKind::Comment(text) = &comment.kind {
+                if let Some(command) = command(text) {
+                    let offset = comment.span.end - command.len();
+                    let command = self.substitute(command, Some(offset), Some(dollar))?;
+                    self.spawn(command, Some(&comment.span), false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Runs a command if the policy allows it, capturing its output or
+    // letting it write to ours. `span` is where its comment was written.
+    // A denied command is an error, which can be caught like any other.
+    fn spawn(
+        &mut self,
+        command: String,
+        span: Option<&Span>,
+        capture: bool,
+    ) -> Result<Output, RuntimeError> {
+        let policy = &self.options.policy;
+        let permitted = if self.options.dry_run {
+            policy.check(&command)
+        } else {
+            policy.permit(&command)
+        };
+        if let Err(denial) = permitted {
+            return Err(ErrorKind::Denied(command, denial).into());
+        }
+
+        if self.options.dry_run {
+            eprintln!("would run: {}", command);
+            return Ok(Output {
+                stdout: String::new(),
+                stderr: String::new(),
+                status: Some(0),
+            });
+        }
+
+        let (started, timer) = (SystemTime::now(), Instant::now());
+        let result = if self.options.shell_session {
+            self.run_in_session(&command, capture)
+        } else if capture {
+            shell::capture(&command, policy)
+        } else {
+            shell::run(&command, policy).map(|status| Output {
+                stdout: String::new(),
+                stderr: String::new(),
+                status: status.code(),
+            })
+        };
+
+        if let Some(audit) = &self.options.audit {
+            let record = Record {
+                command: &command,
+                span,
+                started,
+                duration: timer.elapsed(),
+                status: result.as_ref().ok().and_then(|output| output.status),
+                error: result.as_ref().err().map(ToString::to_string),
+            };
+            if let Err(error) = audit.record(&record) {
+                return Err(ErrorKind::Audit(audit.clone(), error).into());
+            }
+        }
+        result.map_err(|error| ErrorKind::Shell(command, error).into())
+    }
+
+    // After an error, like the shell ending, the session is dropped and the
+    // next command starts a new one.
+    fn run_in_session(&mut self, command: &str, capture: bool) -> io::Result<Output> {
+        let result = self.session()?.run(command, capture);
+        if result.is_err() {
+            self.session = None;
+        }
+        result
+    }
+
+    // Fills in the `$` and `${...}` of a command, quoting every value so it
+    // stays a value and never becomes more of the command. `offset` is where
+    // the command was written, if that's known.
+    fn substitute(
+        &mut self,
+        command: &str,
+        offset: Option<usize>,
+        dollar: Option<&Value>,
+    ) -> Result<String, RuntimeError> {
+        match (self.fill(command, offset.unwrap_or(0), dollar), offset) {
+            // a comment value doesn't know where it was written, so its
+            // errors point at the code running it instead
+            (Err(error), None) => Err(match error.kind {
+                ErrorKind::Parse(_) | ErrorKind::Resolve(_) => {
+                    ErrorKind::Substitution(command.to_string(), error.kind.to_string()).into()
+                }
+                kind => kind.into(),
+            }),
+            (result, _) => result,
+        }
+    }
+
+    fn fill(
+        &mut self,
+        command: &str,
+        offset: usize,
+        dollar: Option<&Value>,
+    ) -> Result<String, RuntimeError> {
+        let parts = parse::command(command, offset).map_err(|error| RuntimeError {
+            span: Some(error.span().clone()),
+            kind: ErrorKind::Parse(error),
+        })?;
+
+        let mut filled = String::new();
+        for part in parts {
+            match part {
+                Part::Text(text) => filled.push_str(&text),
+                Part::Dollar(quotes, span) => {
+                    let value = dollar.cloned().ok_or(RuntimeError {
+                        kind: ErrorKind::UnboundDollar,
+                        span: Some(span),
+                    })?;
+                    filled.push_str(&shell::quote(&self.text(value)?, quotes));
+                }
+                Part::Expr(expr, quotes) => {
+                    resolve::resolve_running(
+                        &expr,
+                        &self.environment.borrow(),
+                        &self.globals.borrow(),
+                    )?;
+                    let value = self.evaluate(&expr)?;
+                    filled.push_str(&shell::quote(&self.text(value)?, quotes));
+                }
+            }
+        }
+        Ok(filled)
+    }
+
+    // How a value is written in strings and commands. Comments are run for
+    // their output like any other command, so the policy, `dry_run` and the
+    // audit log all apply.
+    fn text(&mut self, value: Value) -> Result<String, RuntimeError> {
+        Ok(match value {
+            Value::Str(text) => text,
+            Value::Comment(comment) => format!("{:?}", self.run_comment(&comment)?),
+            value => format!("{:?}", value),
+        })
+    }
+
+    // runs `statements` in a scope of their own
+    fn execute_in(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<Value, Unwind> {
+        let enclosing =
+            std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let result = self.execute_all(statements);
+        self.environment = enclosing;
+        result
+    }
+
+    fn execute(&mut self, statement: &Stmt) -> Result<Value, Unwind> {
+        self.execute_kind(statement)
+            .map_err(|unwind| unwind.at(&statement.span))
+    }
+
+    fn execute_kind(&mut self, statement: &Stmt) -> Result<Value, Unwind> {
+        let span = &statement.span;
+        match &statement.kind {
+            StmtKind::VariableDeclaration { name, value } => {
+                let value = match value {
+                    Some(expr) => self.evaluate_binding(expr)?,
+                    None => Value::Null,
+                };
+                self.define(name, value, false, span);
+                Ok(Value::Null)
+            }
+            StmtKind::ConstDeclaration { name, value } => {
+                let value = self.evaluate_binding(value)?;
+                self.define(name, value, true, span);
+                Ok(Value::Null)
+            }
+            StmtKind::CommentDeclaration { name, value } => match self.evaluate(value)? {
+                comment @ Value::Comment(_) => {
+                    self.define(name, comment, false, span);
+                    Ok(Value::Null)
+                }
+                value => Err(ErrorKind::NotAComment(value).into()),
+            },
+            // the function sees its own binding, it's in the scope it captures
+            StmtKind::FnDeclaration {
+                name,
+                params,
+                body,
+                comments,
+            } => {
+                let function = Function {
+                    name: Some(name.clone()),
+                    params: params.clone(),
+                    body: *body.clone(),
+                    comments: comments.clone(),
+                    span: span.clone(),
+                    closure: self.environment.clone(),
+                };
+                self.define(name, Value::Function(Rc::new(function)), true, span);
+                Ok(Value::Null)
+            }
+            StmtKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.execute(then)
+                } else if let Some(otherwise) = otherwise {
+                    self.execute(otherwise)
+                } else {
+                    Ok(Value::Null)
+                }
+            }
+            StmtKind::Loop { label, body } => loop {
+                match self.execute(body) {
+                    Ok(_) => {}
+                    Err(Unwind::Break(target)) if target.is_none() || target == *label => {
+                        break Ok(Value::Null);
+                    }
+                    Err(Unwind::Continue(target)) if target.is_none() || target == *label => {}
+                    Err(unwind) => break Err(unwind),
+                }
+            },
+            // lists give their items and maps their keys, as they were
+            // when the loop started
+            StmtKind::For {
+                name,
+                iterable,
+                body,
+            } => {
+                let items = match self.evaluate(iterable)? {
+                    Value::List(items) => items.borrow().clone(),
+                    Value::Map(entries) => {
+                        entries.borrow().keys().cloned().map(Value::Str).collect()
+                    }
+                    value => return Err(ErrorKind::NotIterable(value).into()),
+                };
+                for item in items {
+                    let mut scope = Environment::new(self.environment.clone());
+                    let binding = Binding {
+                        value: item,
+                        constant: false,
+                        declared: span.clone(),
+                    };
+                    scope.define(name, binding);
+                    match self.execute_in(std::slice::from_ref(body), scope) {
+                        Ok(_) | Err(Unwind::Continue(None)) => {}
+                        Err(Unwind::Break(None)) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                }
+                Ok(Value::Null)
+            }
+            StmtKind::Block(statements) => {
+                let scope = Environment::new(self.environment.clone());
+                self.execute_in(statements, scope)
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(expr) => self.evaluate(expr)?,
+                    None => Value::Null,
+                };
+                Err(Unwind::Return(value))
+            }
+            StmtKind::Break(label) => Err(Unwind::Break(label.clone())),
+            StmtKind::Continue(label) => Err(Unwind::Continue(label.clone())),
+            StmtKind::Throw(value) => {
+                let exception = Exception {
+                    value: self.evaluate(value)?,
+                    stack: self.calls.iter().rev().cloned().collect(),
+                };
+                Err(ErrorKind::Thrown(exception).into())
+            }
+            // runtime errors can be caught like thrown values
+            StmtKind::Try {
+                body,
+                name,
+                handler,
+            } => match self.execute(body) {
+                Err(Unwind::Error(error)) => {
+                    // the caught value is only bound inside the handler
+                    let mut scope = Environment::new(self.environment.clone());
+                    if let Some(name) = name {
+                        let binding = Binding {
+                            value: error.to_value(),
+                            constant: false,
+                            declared: span.clone(),
+                        };
+                        scope.define(name, binding);
+                    }
+                    self.execute_in(std::slice::from_ref(handler), scope)
+                }
+                result => result,
+            },
+            StmtKind::Comment(text) => {
+                if self.options.without_comments {
+                    return Ok(Value::Null);
+                }
+
+                if let Some(command) = ast::shell_command(text) {
+                    let offset = span.end - command.len();
+                    let command = self.substitute(command, Some(offset), None)?;
+                    self.spawn(command, Some(span), false)?;
+                }
+                Ok(Value::Null)
+            }
+            // a comment value standing on its own is run for its output
+            StmtKind::Expr(expr) => match self.evaluate(expr)? {
+                Value::Comment(text) => Ok(self.run_comment(&text)?),
+                value => Ok(value),
+            },
+        }
+    }
+
+    /// Runs a comment value: shell and failure comments give what their
+    /// command wrote and how it exited, plain comments give their text.
+    pub fn run_comment(&mut self, text: &str) -> Result<Value, RuntimeError> {
+        self.run_comment_at(text, None)
+    }
+
+    // `written` is the span of the comment, when it's known
+    fn run_comment_at(
+        &mut self,
+        text: &str,
+        written: Option<&Span>,
+    ) -> Result<Value, RuntimeError> {
+        let command = match ast::shell_command(text).or_else(|| ast::failure_command(text)) {
+            Some(command) => command,
+            None => return Ok(Value::Str(text.to_string())),
+        };
+
+        if self.options.without_comments {
+            return Ok(Value::Null);
+        }
+
+        let offset = written.map(|span| span.end - command.len());
+        let command = self.substitute(command, offset, None)?;
+        let output = self.spawn(command, written, true)?;
+        Ok(Value::Output(Rc::new(output)))
+    }
+
+    // What `let` and `const` bind: a command comment is run, and its result
+    // is bound instead. `comment` declarations keep it to run later.
+    fn evaluate_binding(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match self.evaluate(expr)? {
+            Value::Comment(text)
+                if ast::shell_command(&text)
+                    .or_else(|| ast::failure_command(&text))
+                    .is_some() =>
+            {
+                // a comment written right here knows where it was written
+                let written = match &expr.kind {
+                    ExprKind::Literal(_) => Some(&expr.span),
+                    _ => None,
+                };
+                self.run_comment_at(&text, written)
+            }
+            value => Ok(value),
+        }
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        self.evaluate_kind(expr)
+            .map_err(|error| error.at(&expr.span))
+    }
+
+    fn evaluate_kind(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        match &expr.kind {
+            ExprKind::Binary { left, op, right } => self.binary(left, op, right),
+            ExprKind::Unary { op, expr } => {
+                let value = self.evaluate(expr)?;
+                match (op, value) {
+                    (Token::Minus, Value::Num(value)) => Ok(Value::Num(-value)),
+                    (Token::Bang, value) => Ok(Value::Bool(!value.is_truthy())),
+                    (op, value) => Err(ErrorKind::InvalidOperand(op.clone(), value).into()),
+                }
+            }
+            ExprKind::Literal(value) => Ok(value.clone()),
+            ExprKind::Grouping(expr) => self.evaluate(expr),
+            ExprKind::Variable(name) => self
+                .get(name, expr.slot.get())
+                .ok_or_else(|| ErrorKind::UndefinedVariable(name.clone()).into()),
+            ExprKind::Assignment(name, value) => {
+                let value = self.evaluate(value)?;
+                match expr.slot.get() {
+                    Some(slot) => {
+                        self.environment
+                            .borrow_mut()
+                            .assign_at(slot, name, value.clone())?
+                    }
+                    None => self.globals.borrow_mut().assign(name, value.clone())?,
+                }
+                Ok(value)
+            }
+            // builtins aren't values, a name bound to nothing is one of them
+            ExprKind::Call { callee, args } => match &callee.kind {
+                ExprKind::Variable(name) if self.get(name, callee.slot.get()).is_none() => {
+                    let args = self.arguments(args)?;
+                    self.call(name, args)
+                }
+                _ => {
+                    let callee = self.evaluate(callee)?;
+                    let args = self.arguments(args)?;
+                    self.call_value(callee, args)
+                }
+            },
+            ExprKind::Get { object, name } => match (self.evaluate(object)?, name.as_str()) {
+                (Value::Comment(text), "text") => Ok(Value::Str(text)),
+                (Value::Output(output), "stdout") => Ok(Value::Str(output.stdout.clone())),
+                (Value::Output(output), "stderr") => Ok(Value::Str(output.stderr.clone())),
+                (Value::Output(output), "status") => Ok(output
+                    .status
+                    .map_or(Value::Null, |code| Value::Num(code as f64))),
+                (Value::Output(output), "ok") => Ok(Value::Bool(output.success())),
+                (value, _) => Err(ErrorKind::UndefinedProperty(value, name.clone()).into()),
+            },
+            ExprKind::List(items) => Ok(Value::list(self.arguments(items)?)),
+            // values are written the way `print` writes them
+            ExprKind::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    let part = self.evaluate(part)?;
+                    text.push_str(&self.text(part)?);
+                }
+                Ok(Value::Str(text))
+            }
+            ExprKind::Map(entries) => {
+                let mut map = BTreeMap::new();
+                for (key, value) in entries {
+                    let key = match self.evaluate(key)? {
+                        Value::Str(key) => key,
+                        key => return Err(ErrorKind::InvalidKey(key).into()),
+                    };
+                    map.insert(key, self.evaluate(value)?);
+                }
+                Ok(Value::map(map))
+            }
+            ExprKind::Index { object, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                match (&object, &index) {
+                    (Value::List(items), Value::Num(position)) => {
+                        let items = items.borrow();
+                        Ok(items[list_position(&object, *position, items.len())?].clone())
+                    }
+                    (Value::Map(entries), Value::Str(key)) => entries
+                        .borrow()
+                        .get(key)
+                        .cloned()
+                        .ok_or_else(|| ErrorKind::UndefinedKey(key.clone()).into()),
+                    _ => Err(ErrorKind::InvalidIndex(object, index).into()),
+                }
+            }
+            ExprKind::IndexAssignment {
+                object,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                match (&object, &index) {
+                    (Value::List(items), Value::Num(position)) => {
+                        let mut items = items.borrow_mut();
+                        let position = list_position(&object, *position, items.len())?;
+                        items[position] = value.clone();
+                    }
+                    (Value::Map(entries), Value::Str(key)) => {
+                        entries.borrow_mut().insert(key.clone(), value.clone());
+                    }
+                    _ => return Err(ErrorKind::InvalidIndex(object, index).into()),
+                }
+                Ok(value)
+            }
+            ExprKind::Function { params, body } => {
+                let function = Function {
+                    name: None,
+                    params: params.clone(),
+                    body: *body.clone(),
+                    comments: Vec::new(),
+                    span: expr.span.clone(),
+                    closure: self.environment.clone(),
+                };
+                Ok(Value::Function(Rc::new(function)))
+            }
+        }
+    }
+
+    fn arguments(&mut self, args: &[Expr]) -> Result<Vec<Value>, RuntimeError> {
+        args.iter().map(|arg| self.evaluate(arg)).collect()
+    }
+
+    // locals are found through the slot the resolver gave them
+    fn get(&self, name: &str, slot: Option<Slot>) -> Option<Value> {
+        match slot {
+            Some(slot) => self.environment.borrow().get_at(slot),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: Value, constant: bool, declared: &Span) {
+        let binding = Binding {
+            value,
+            constant,
+            declared: declared.clone(),
+        };
+        self.environment.borrow_mut().define(name, binding);
+    }
+
+    fn binary(&mut self, left: &Expr, op: &Token, right: &Expr) -> Result<Value, RuntimeError> {
+        let left = self.evaluate(left)?;
+
+        // `&&` and `||` short-circuit and yield one of their operands
+        match op {
+            Token::And if !left.is_truthy() => return Ok(left),
+            Token::Or if left.is_truthy() => return Ok(left),
+            Token::And | Token::Or => return self.evaluate(right),
+            _ => {}
+        }
+
+        let right = self.evaluate(right)?;
+        let value = match (op, left, right) {
+            (Token::Plus, Value::Num(a), Value::Num(b)) => Value::Num(a + b),
+            (Token::Plus, Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
+            (Token::Minus, Value::Num(a), Value::Num(b)) => Value::Num(a - b),
+            (Token::Star, Value::Num(a), Value::Num(b)) => Value::Num(a * b),
+            (Token::Slash, Value::Num(a), Value::Num(b)) => Value::Num(a / b),
+            (Token::Greater, Value::Num(a), Value::Num(b)) => Value::Bool(a > b),
+            (Token::GreaterEqual, Value::Num(a), Value::Num(b)) => Value::Bool(a >= b),
+            (Token::Less, Value::Num(a), Value::Num(b)) => Value::Bool(a < b),
+            (Token::LessEqual, Value::Num(a), Value::Num(b)) => Value::Bool(a <= b),
+            (Token::EqualEqual, a, b) => Value::Bool(a == b),
+            (Token::BangEqual, a, b) => Value::Bool(a != b),
+            (op, a, b) => return Err(ErrorKind::InvalidOperands(op.clone(), a, b).into()),
+        };
+        Ok(value)
+    }
+}
+
+// where `index` points in `list`, which has `len` items
+fn list_position(list: &Value, index: f64, len: usize) -> Result<usize, ErrorKind> {
+    if index < 0f64 || index.fract() != 0f64 {
+        return Err(ErrorKind::InvalidIndex(list.clone(), Value::Num(index)));
+    }
+    match index as usize {
+        position if position < len => Ok(position),
+        position => Err(ErrorKind::IndexOutOfRange(position, len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Parser;
+    use crate::testing::TempDir;
+
+    fn run(program: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        let statements = Parser::new(program).parse()?;
+        Ok(Interpreter::new().interpret(&statements)?)
+    }
+
+    #[test]
+    fn arithmetic() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("1+2*3-4")?, Value::Num(3f64));
+        assert_eq!(run("(1+2)*3")?, Value::Num(9f64));
+        assert_eq!(run("-(4/2)")?, Value::Num(-2f64));
+        Ok(())
+    }
+
+    #[test]
+    fn strings() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run(r#" "foo" + "bar" "#)?, Value::Str("foobar".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn comparison() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("5 < 3")?, Value::Bool(false));
+        assert_eq!(run("3 <= 3")?, Value::Bool(true));
+        assert_eq!(run(r#" "a" == "a" "#)?, Value::Bool(true));
+        assert_eq!(run("1 != null")?, Value::Bool(true));
+        assert_eq!(run("!null")?, Value::Bool(true));
+        Ok(())
+    }
+
+    #[test]
+    fn logical() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("null || 2")?, Value::Num(2f64));
+        assert_eq!(run("let f = null false && f()")?, Value::Bool(false));
+        assert_eq!(run("1 && 2")?, Value::Num(2f64));
+        Ok(())
+    }
+
+    #[test]
+    fn variables() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("let foo = 1 foo = foo + 1 foo")?, Value::Num(2f64));
+        assert_eq!(run("const foo = 1 foo")?, Value::Num(1f64));
+        assert_eq!(run("let foo foo")?, Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn if_statement() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let result = null
+        if 5 < 3 {
+            result = "then"
+        } else {
+            result = "otherwise"
+        }
+        result
+        "#;
+        assert_eq!(run(program)?, Value::Str("otherwise".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn shell_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("shell");
+        let path = dir.join("touched");
+        let program = format!(
+            "# touch {0}\nif 5 < 3 {{\n# > sudo shutdown\n}} else {{\n# > touch {0}\n}}",
+            path.display()
+        );
+
+        assert!(!path.exists());
+        run(&program)?;
+        assert!(path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn function_hooks() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("hooks");
+        let program = format!(
+            "# > touch {0}/$\n# ! touch {0}/failed\nfn test(result) {{ result }}\n\
+             # ! touch {0}/thrown\nfn fail() {{ throw \"failed\" }}",
+            dir.path().display()
+        );
+        let statements = Parser::new(&program).parse()?;
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(&statements)?;
+
+        let value = interpreter.call("test", vec![Value::Str("passed".to_string())])?;
+        assert!(matches!(
+            interpreter.call("test", vec![]),
+            Err(RuntimeError {
+                kind: ErrorKind::Arity(_, 1, 0),
+                ..
+            })
+        ));
+        assert_eq!(value, Value::Str("passed".to_string()));
+        assert!(dir.join("passed").exists());
+        assert!(!dir.join("failed").exists());
+
+        assert!(interpreter.call("fail", vec![]).is_err());
+        assert!(dir.join("thrown").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn comment_values() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        comment x = # > echo "hello, world"
+        let y = x
+        y.stdout
+        "#;
+        assert_eq!(run(program)?, Value::Str("hello, world".to_string()));
+
+        let program = r#"
+        let r = # > echo out; echo err >&2; exit 3
+        let parts = [r.stdout, r.stderr, r.status, r.ok]
+        parts
+        "#;
+        assert_eq!(
+            run(program)?,
+            Value::list(vec![
+                Value::Str("out".to_string()),
+                Value::Str("err".to_string()),
+                Value::Num(3f64),
+                Value::Bool(false),
+            ])
+        );
+        assert_eq!(
+            run("const r = # > true
+if r.ok { \"passed\" }")?,
+            Value::Str("passed".to_string())
+        );
+
+        let program = "comment x = # just a note\nlet y = x\ny == x";
+        assert_eq!(run(program)?, Value::Bool(true));
+
+        let statements = Parser::new("comment x = 1").parse()?;
+        assert!(matches!(
+            Interpreter::new().interpret(&statements),
+            Err(RuntimeError {
+                kind: ErrorKind::NotAComment(Value::Num(_)),
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn loops() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let i = 0
+        let total = 0
+        loop outer {
+            i = i + 1
+            if i > 10 { break }
+            if i == 3 { continue }
+            let j = 0
+            loop {
+                j = j + 1
+                if j > i { continue outer }
+                total = total + 1
+            }
+        }
+        total
+        "#;
+        assert_eq!(run(program)?, Value::Num(52f64));
+        Ok(())
+    }
+
+    #[test]
+    fn returns() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        fn find(limit) {
+            let i = 0
+            loop {
+                if i * i > limit { return i }
+                i = i + 1
+            }
+        }
+        fn nothing() { return }
+        find(50) + 1
+        "#;
+        assert_eq!(run(program)?, Value::Num(9f64));
+        assert_eq!(
+            run("fn nothing() { return 1 + 1 }\nnothing()")?,
+            Value::Num(2f64)
+        );
+        assert_eq!(run("fn nothing() { return }\nnothing()")?, Value::Null);
+
+        let statements = Parser::new("loop { break missing }").parse()?;
+        assert!(matches!(
+            Interpreter::new().interpret(&statements),
+            Err(RuntimeError {
+                kind: ErrorKind::Resolve(errors),
+                ..
+            }) if matches!(errors[..], [ResolveError::UnknownLabel(_, _)])
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn exceptions() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        fn check(value) {
+            if value > 1 { throw "too big" }
+            value
+        }
+        let caught = null
+        try {
+            check(1)
+            check(2)
+            caught = "unreachable"
+        } catch e {
+            caught = e
+        }
+        caught
+        "#;
+        assert_eq!(run(program)?, Value::Str("too big".to_string()));
+
+        let program = "try { 1 + null } catch e { e }";
+        assert_eq!(
+            run(program)?,
+            Value::Str("invalid operands for Plus: `1` and `null`".to_string())
+        );
+
+        let program = "loop { try { break } catch { 1 } } 2";
+        assert_eq!(run(program)?, Value::Num(2f64));
+        Ok(())
+    }
+
+    #[test]
+    fn uncaught_exceptions() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        fn inner() { throw "wrong" }
+        fn outer() { inner() }
+        outer()
+        "#;
+        let statements = Parser::new(program).parse()?;
+        let mut interpreter = Interpreter::new();
+
+        match interpreter.interpret(&statements) {
+            Err(RuntimeError {
+                kind: ErrorKind::Thrown(exception),
+                ..
+            }) => {
+                assert_eq!(exception.value, Value::Str("wrong".to_string()));
+                assert_eq!(exception.stack, vec!["inner", "outer"]);
+            }
+            result => panic!("expected an exception, got {:?}", result),
+        }
+        assert!(Rc::ptr_eq(&interpreter.environment, &interpreter.globals));
+        assert!(interpreter.calls.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn substitution() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let name = "it's; rm -rf ~"
+        fn greet(greeting) {
+            let out = # > printf '%s|%s|' "${greeting}, ${name}" ${[1, 2]}'$$HOME'
+            out.stdout
+        }
+        greet("hi")
+        "#;
+        assert_eq!(
+            run(program)?,
+            Value::Str("hi, it's; rm -rf ~|[1, 2]$HOME|".to_string())
+        );
+
+        // a `\` before a value can't escape its quotes
+        let dir = TempDir::new("substitution");
+        let program = format!(
+            r#"
+            let bare = "; touch {0}/bare #"
+            let double = "\"; touch {0}/double #"
+            let out = # > printf '%s|' \${{bare}} "\${{double}}" \$$HOME
+            out.stdout
+            "#,
+            dir.path().display()
+        );
+        assert_eq!(
+            run(&program)?,
+            Value::Str(format!(
+                "\\; touch {0}/bare #|\\\"; touch {0}/double #|$HOME|",
+                dir.path().display()
+            ))
+        );
+        assert!(!dir.join("bare").exists() && !dir.join("double").exists());
+
+        // a comment value sees the names around where it's run
+        let program = r#"
+        comment show = # > echo ${x}
+        fn f(x) { run(show) }
+        f(1).stdout
+        "#;
+        assert_eq!(run(program)?, Value::Str("1".to_string()));
+
+        let statements = Parser::new(
+            "let x = 1
+# > echo $",
+        )
+        .parse()?;
+        assert!(matches!(
+            Interpreter::new().interpret(&statements),
+            Err(RuntimeError {
+                kind: ErrorKind::UnboundDollar,
+                span: Some(Span { start: 19, end: 20 }),
+            })
+        ));
+        let statements = Parser::new("let x = # > echo ${y}").parse()?;
+        match Interpreter::new().interpret(&statements) {
+            Err(RuntimeError {
+                kind: ErrorKind::Resolve(errors),
+                ..
+            }) => assert_eq!(
+                errors,
+                [ResolveError::UndefinedVariable("y".to_string(), 19..20)]
+            ),
+            result => panic!("expected an undefined variable, got {:?}", result),
+        }
+        let statements = Parser::new(
+            "comment x = # > echo ${y}
+run(x)",
+        )
+        .parse()?;
+        assert!(matches!(
+            Interpreter::new().interpret(&statements),
+            Err(RuntimeError {
+                kind: ErrorKind::Substitution(_, _),
+                span: Some(Span { start: 26, end: 32 }),
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn denied_commands() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let target = "/"
+        try {
+            # > echo removing && rm -rf ${target}
+        } catch error {
+            error
+        }
+        "#;
+        let statements = Parser::new(program).parse()?;
+        let mut interpreter = Interpreter::with_options(Options {
+            policy: Policy {
+                deny: vec!["rm".to_string()],
+                ..Policy::default()
+            },
+            ..Options::default()
+        });
+        assert_eq!(
+            interpreter.interpret(&statements)?,
+            Value::Str(
+                "`echo removing && rm -rf '/'` was not run, `rm` is a denied program".to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("dry-run");
+        let path = dir.join("touched");
+        let program = format!(
+            "let file = \"{}\"\n# > touch ${{file}}\nlet result = # > false\nresult.ok",
+            path.display()
+        );
+        let statements = Parser::new(&program).parse()?;
+        let mut interpreter = Interpreter::with_options(Options {
+            dry_run: true,
+            ..Options::default()
+        });
+        assert_eq!(interpreter.interpret(&statements)?, Value::Bool(true));
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn interpolated_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("interpolated");
+        let path = dir.join("touched");
+        let program = format!(
+            "comment touch = # > touch {}\n\"ran ${{touch}}\"",
+            path.display()
+        );
+        let statements = Parser::new(&program).parse()?;
+        let mut interpreter = Interpreter::with_options(Options {
+            dry_run: true,
+            ..Options::default()
+        });
+        interpreter.interpret(&statements)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn audit_log() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("audit-log");
+        let path = dir.join("audit.jsonl");
+        let program = "# > true\ntry {\nlet x = # > exit 3\n} catch {}";
+        let statements = Parser::new(program).parse()?;
+        let mut interpreter = Interpreter::with_options(Options {
+            audit: Some(AuditLog::new(&path)),
+            ..Options::default()
+        });
+        interpreter.interpret(&statements)?;
+
+        let log = std::fs::read_to_string(&path)?;
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(r#""span":[0,8],"command":"true","status":0,"#));
+        assert!(lines[1].contains(r#""span":[23,33],"command":"exit 3","status":3,"#));
+        Ok(())
+    }
+
+    #[test]
+    fn shell_session() -> Result<(), Box<dyn std::error::Error>> {
+        let program =
+            "# > cd / && export GREETING=hi\nlet here = # > echo \"$$GREETING\" && pwd\nhere.stdout";
+        let statements = Parser::new(program).parse()?;
+        let mut interpreter = Interpreter::with_options(Options {
+            shell_session: true,
+            ..Options::default()
+        });
+        assert_eq!(
+            interpreter.interpret(&statements)?,
+            Value::Str("hi\n/".to_string())
+        );
+        assert_eq!(interpreter.shell_cwd()?, PathBuf::from("/"));
+
+        interpreter.restart_shell();
+        assert_eq!(interpreter.shell_cwd()?, std::env::current_dir()?);
+
+        // a command that ends the shell fails, and the next one gets a new shell
+        let program = "# > cd /\ntry {\n# > exit 3\n} catch error {\nerror\n}";
+        let statements = Parser::new(program).parse()?;
+        match interpreter.interpret(&statements)? {
+            Value::Str(error) => assert!(error.ends_with("the shell session ended with status 3")),
+            value => panic!("expected an error, got {:?}", value),
+        }
+        assert_eq!(interpreter.shell_cwd()?, std::env::current_dir()?);
+        Ok(())
+    }
+
+    #[test]
+    fn failure_hooks() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("failure");
+        let program = format!(
+            r#"
+            # > echo "$" > {0}/passed
+            # ! echo "$" > {0}/failed
+            fn test(fail) {{
+                if fail {{
+                    throw "wrong"
+                }} else {{
+                    return "pass"
+                }}
+            }}
+            test(false)
+            try {{ test(true) }} catch {{}}
+            "#,
+            dir.path().display()
+        );
+        run(&program)?;
+
+        assert_eq!(std::fs::read_to_string(dir.join("passed"))?, "pass\n");
+        assert_eq!(std::fs::read_to_string(dir.join("failed"))?, "wrong\n");
+        Ok(())
+    }
+
+    #[test]
+    fn calls() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        fn add(a, b) { a + b }
+        fn twice(a) { add(a, a) }
+        twice(add(1, 2))
+        "#;
+        assert_eq!(run(program)?, Value::Num(6f64));
+
+        let program = "comment x = # > echo hi\nx.text";
+        assert_eq!(run(program)?, Value::Str("> echo hi".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn interpolation() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let name = "world"
+        let xs = [1, "two"]
+        "hello ${name}, ${len(xs)} items: ${xs} ${null}"
+        "#;
+        assert_eq!(
+            run(program)?,
+            Value::Str("hello world, 2 items: [1, \"two\"] null".to_string())
+        );
+        assert!(run(r#""${undefined}""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn collections() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let xs = [1, 2, 3]
+        let ys = xs
+        ys[0] = 10
+        xs[0] + xs[2]
+        "#;
+        assert_eq!(run(program)?, Value::Num(13f64));
+
+        let program = r#"
+        let m = { "a": 1, "b": [2] }
+        m["c"] = m["b"][0] + 1
+        m["c"]
+        "#;
+        assert_eq!(run(program)?, Value::Num(3f64));
+
+        assert_eq!(
+            run("[1, [2], { \"a\": null }] == [1, [2], { \"a\": null }]")?,
+            Value::Bool(true)
+        );
+        assert_eq!(run("{ \"a\": 1 } == { \"a\": 2 }")?, Value::Bool(false));
+        assert_eq!(run("{ \"a\": 1 }")?, run("let m = {} m[\"a\"] = 1 m")?);
+
+        let mut interpreter = Interpreter::new();
+        let mut error = |program| {
+            let statements = Parser::new(program).parse().unwrap();
+            interpreter.interpret(&statements).unwrap_err().kind
+        };
+        assert!(matches!(error("[1][1]"), ErrorKind::IndexOutOfRange(1, 1)));
+        assert!(matches!(error("[1][0.5]"), ErrorKind::InvalidIndex(_, _)));
+        assert!(matches!(error("let e = {}[\"a\"]"), ErrorKind::UndefinedKey(key) if key == "a"));
+        assert!(matches!(
+            error("let e = { 1: 2 }"),
+            ErrorKind::InvalidKey(Value::Num(_))
+        ));
+        assert!(matches!(error("for x in 1 {}"), ErrorKind::NotIterable(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn for_loops() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let sum = 0
+        for x in [1, 2, 3, 4, 5] {
+            if x == 2 { continue }
+            if x == 4 { break }
+            sum = sum + x
+        }
+        sum
+        "#;
+        assert_eq!(run(program)?, Value::Num(4f64));
+
+        let program = r#"
+        let keys = ""
+        for key in { "b": 1, "a": 2 } { keys = keys + key }
+        keys
+        "#;
+        assert_eq!(run(program)?, Value::Str("ab".to_string()));
+
+        // a map and a filter written in the language itself
+        let program = r#"
+        fn map(xs, f) {
+            let ys = []
+            for x in xs { push(ys, f(x)) }
+            ys
+        }
+        fn filter(xs, keep) {
+            let ys = []
+            for x in xs { if keep(x) { push(ys, x) } }
+            ys
+        }
+        filter(map([1, 2, 3], fn (x) { x * 2 }), fn (x) { x > 2 })
+        "#;
+        assert_eq!(
+            run(program)?,
+            Value::list(vec![Value::Num(4f64), Value::Num(6f64)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn closures() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        fn counter() {
+            let count = 0
+            fn () { count = count + 1 }
+        }
+        let next = counter()
+        next()
+        next()
+        let other = counter()
+        other()
+        next()
+        "#;
+        assert_eq!(run(program)?, Value::Num(3f64));
+
+        let program = r#"
+        fn apply(f, x) { f(x) }
+        fn compose(f, g) { fn (x) { f(g(x)) } }
+        fn double(x) { x * 2 }
+        let inc = fn (x) { x + 1 }
+        apply(compose(inc, double), 5)
+        "#;
+        assert_eq!(run(program)?, Value::Num(11f64));
+
+        assert_eq!(run("fn (a, b) { a - b }(3, 1)")?, Value::Num(2f64));
+        assert_eq!(
+            run("{ fn fact(n) { if n < 2 { 1 } else { n * fact(n - 1) } } fact(5) }")?,
+            Value::Num(120f64)
+        );
+        assert_eq!(run("fn f() {} f == f")?, Value::Bool(true));
+        assert_eq!(run("fn () {} == fn () {}")?, Value::Bool(false));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_calls() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(&Parser::new("fn add(a, b) { a + b } let x = 1").parse()?)?;
+
+        let mut call = |program| interpreter.interpret(&Parser::new(program).parse().unwrap());
+        assert!(matches!(
+            call("add(1)"),
+            Err(RuntimeError {
+                kind: ErrorKind::Arity(_, 2, 1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            call("x()"),
+            Err(RuntimeError {
+                kind: ErrorKind::NotCallable(Value::Num(_)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            call("(1)(2)"),
+            Err(RuntimeError {
+                kind: ErrorKind::NotCallable(Value::Num(_)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            call("foo()"),
+            Err(RuntimeError {
+                kind: ErrorKind::Resolve(errors),
+                ..
+            }) if errors == [ResolveError::UndefinedFunction("foo".to_string(), 0..3)]
+        ));
+        assert!(matches!(
+            call("x.y"),
+            Err(RuntimeError {
+                kind: ErrorKind::UndefinedProperty(_, _),
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn without_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new("without");
+        let path = dir.join("touched");
+        let program = format!(
+            "# > touch {0}\n# > touch {0}\nfn test() {{ 1 }}\nlet foo = 2",
+            path.display()
+        );
+        let statements = Parser::new(&program).parse()?;
+        let mut interpreter = Interpreter::with_options(Options {
+            without_comments: true,
+            ..Options::default()
+        });
+
+        interpreter.interpret(&statements)?;
+        interpreter.call("test", vec![])?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let statements = Parser::new("foo = 1").parse().unwrap();
+        assert!(matches!(
+            Interpreter::new().interpret(&statements),
+            Err(RuntimeError { kind: ErrorKind::Resolve(errors), .. })
+                if errors == vec![ResolveError::UndefinedVariable("foo".to_string(), 0..7)]
+        ));
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let statements = Parser::new(r#" 1 + "foo" "#).parse().unwrap();
+        assert!(matches!(
+            Interpreter::new().interpret(&statements),
+            Err(RuntimeError {
+                kind: ErrorKind::InvalidOperands(Token::Plus, _, _),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn error_spans() {
+        // the innermost node that failed is reported, not the whole statement
+        let statements = Parser::new("let x = 1 + (2 * foo)").parse().unwrap();
+        let error = Interpreter::new().interpret(&statements).unwrap_err();
+        assert_eq!(error.span, Some(17..20));
+
+        let statements = Parser::new("fn f() { 1 + \"a\" }\nf()").parse().unwrap();
+        let error = Interpreter::new().interpret(&statements).unwrap_err();
+        assert_eq!(error.span, Some(9..16));
+    }
+
+    #[test]
+    fn globals() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::new();
+        let program = "const a = 1\nlet b = 2\nconst b = 3\nlet a = 4\nfn f(c) { const d = c }";
+        interpreter.interpret(&Parser::new(program).parse()?)?;
+        interpreter.interpret(&Parser::new("f(5)").parse()?)?;
+
+        let globals: Vec<(String, bool)> = interpreter
+            .globals()
+            .into_iter()
+            .map(|(name, binding)| (name, binding.constant))
+            .collect();
+        assert_eq!(
+            globals,
+            [
+                ("a".to_string(), false),
+                ("b".to_string(), true),
+                ("f".to_string(), true),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scopes() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("let a = 1 { let a = 2 a = 3 } a")?, Value::Num(1f64));
+        assert_eq!(run("let a = 1 { a = 2 } a")?, Value::Num(2f64));
+        assert_eq!(run("let a a")?, Value::Null);
+        assert_eq!(
+            run("fn f(a) { { let a = a * 2 } a } f(3)")?,
+            Value::Num(3f64)
+        );
+        assert!(run("{ let b = 1 } b").is_err());
+        assert!(run("try { throw 1 } catch e {} e").is_err());
+
+        let statements = Parser::new("const a = 1\n{ a = 2 }").parse()?;
+        match Interpreter::new().interpret(&statements) {
+            Err(RuntimeError {
+                kind: ErrorKind::Resolve(errors),
+                span,
+            }) => {
+                assert_eq!(
+                    errors,
+                    vec![ResolveError::AssignToConstant(
+                        "a".to_string(),
+                        0..11,
+                        14..19
+                    )]
+                );
+                assert_eq!(span, Some(14..19));
+            }
+            result => panic!("expected a const error, got {:?}", result),
+        }
+        Ok(())
+    }
+}
@@ -3,7 +3,9 @@ use std::fmt;
 
 pub struct Lexer<'source> {
     lexer: LogosLexer<'source, Token>,
-    peeked: Option<Option<Token>>,
+    peeked: Option<Option<(Token, Span)>>,
+    // span of the last token returned by `next`
+    span: Span,
 }
 
 impl<'source> Lexer<'source> {
@@ -11,20 +13,43 @@ impl<'source> Lexer<'source> {
         Self {
             lexer: Token::lexer(source),
             peeked: None,
+            span: 0..0,
         }
     }
 
     pub fn peek(&mut self) -> Option<&Token> {
         let lexer = &mut self.lexer;
-        self.peeked.get_or_insert_with(|| lexer.next()).as_ref()
+        self.peeked
+            .get_or_insert_with(|| lexer.next().map(|token| (token, lexer.span())))
+            .as_ref()
+            .map(|(token, _)| token)
     }
 
-    pub fn span(&mut self) -> Span {
-        self.lexer.span()
+    pub fn peek_span(&mut self) -> Option<Span> {
+        self.peek();
+        self.peeked.clone().flatten().map(|(_, span)| span)
     }
 
-    pub fn slice(&mut self) -> &str {
-        self.lexer.slice()
+    /// The token `n` tokens after the next one, without moving the lexer.
+    pub fn peek_nth(&mut self, n: usize) -> Option<Token> {
+        if n == 0 {
+            return self.peek().cloned();
+        }
+        self.peek();
+        let mut lexer = self.lexer.clone();
+        lexer.nth(n - 1)
+    }
+
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    pub fn slice(&self) -> &'source str {
+        &self.lexer.source()[self.span()]
+    }
+
+    pub fn source(&self) -> &'source str {
+        self.lexer.source()
     }
 }
 
@@ -32,11 +57,14 @@ impl<'source> Iterator for Lexer<'source> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        if let Some(peeked) = self.peeked.take() {
-            peeked
-        } else {
-            self.lexer.next()
-        }
+        let next = match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.lexer.next().map(|token| (token, self.lexer.span())),
+        };
+
+        let (token, span) = next?;
+        self.span = span;
+        Some(token)
     }
 }
 
@@ -48,8 +76,11 @@ pub enum Token {
 	#[token(")")] RightParen,
 	#[token("{")] LeftBrace,
 	#[token("}")] RightBrace,
+	#[token("[")] LeftBracket,
+	#[token("]")] RightBracket,
 	#[token(",")] Comma,
 	#[token(";")] SemiColon,
+	#[token(":")] Colon,
 	#[token(".")] Dot,
 
     // Operators
@@ -76,12 +107,15 @@ pub enum Token {
 	#[token("let")] Let,
 	#[token("const")] Const,
 	#[token("fn")] Func,
+	#[token("comment")] CommentKeyword,
 
 	#[token("null")] Null,
 	#[token("true")] True,
 	#[token("false")] False,
 
 	#[token("loop")] Loop,
+	#[token("for")] For,
+	#[token("in")] In,
 	#[token("break")] Break,
 	#[token("continue")] Continue,
 	#[token("return")] Return,
@@ -89,14 +123,18 @@ pub enum Token {
 	#[token("if")] If,
 	#[token("else")] Else,
 
+	#[token("throw")] Throw,
+	#[token("try")] Try,
+	#[token("catch")] Catch,
+
     // Literals
     #[regex(r"#[^\n\r]*")]
     Comment,
 
-    #[regex(r"[_A-z]\w*")]
+    #[regex(r"[_A-Za-z]\w*")]
     Ident,
 
-    #[regex(r#""([^"\\]|\\t|\\u|\\n|\\")*""#)]
+    #[token("\"", string)]
     Str,
 
     #[regex(r"\d+(\.*\d+)?")]
@@ -107,9 +145,117 @@ pub enum Token {
     Error,
 }
 
+// Finds the end of a string literal, whose opening quote was just read.
+// Quotes inside an interpolated `${...}` belong to the embedded expression.
+fn string(lex: &mut LogosLexer<Token>) -> bool {
+    match string_end(lex.remainder()) {
+        Some(len) => {
+            lex.bump(len);
+            true
+        }
+        None => false,
+    }
+}
+
+// length of the rest of a string literal, closing quote included
+fn string_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'"' => return Some(index + 1),
+            b'\\' => index += 1,
+            b'$' if bytes.get(index + 1) == Some(&b'{') => {
+                index += 2 + interpolation_end(&text[index + 2..])?;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Length of the expression embedded in a string by `${`, closing `}`
+/// included, `None` if it isn't closed.
+pub fn interpolation_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'"' => {
+                index += 1 + string_end(&text[index + 1..])?;
+                continue;
+            }
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(index + 1),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+/// The keywords of the language, as written.
+pub const KEYWORDS: &[&str] = &[
+    "let", "const", "fn", "comment", "null", "true", "false", "loop", "for", "in", "break",
+    "continue", "return", "if", "else", "throw", "try", "catch",
+];
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self)
+        let text = match self {
+            Token::LeftParen => "`(`",
+            Token::RightParen => "`)`",
+            Token::LeftBrace => "`{`",
+            Token::RightBrace => "`}`",
+            Token::LeftBracket => "`[`",
+            Token::RightBracket => "`]`",
+            Token::Comma => "`,`",
+            Token::SemiColon => "`;`",
+            Token::Colon => "`:`",
+            Token::Dot => "`.`",
+            Token::Minus => "`-`",
+            Token::Plus => "`+`",
+            Token::Slash => "`/`",
+            Token::Star => "`*`",
+            Token::Bang => "`!`",
+            Token::BangEqual => "`!=`",
+            Token::Equal => "`=`",
+            Token::EqualEqual => "`==`",
+            Token::Greater => "`>`",
+            Token::GreaterEqual => "`>=`",
+            Token::Less => "`<`",
+            Token::LessEqual => "`<=`",
+            Token::And => "`&&`",
+            Token::Or => "`||`",
+            Token::Let => "`let`",
+            Token::Const => "`const`",
+            Token::Func => "`fn`",
+            Token::CommentKeyword => "`comment`",
+            Token::Null => "`null`",
+            Token::True => "`true`",
+            Token::False => "`false`",
+            Token::Loop => "`loop`",
+            Token::For => "`for`",
+            Token::In => "`in`",
+            Token::Break => "`break`",
+            Token::Continue => "`continue`",
+            Token::Return => "`return`",
+            Token::If => "`if`",
+            Token::Else => "`else`",
+            Token::Throw => "`throw`",
+            Token::Try => "`try`",
+            Token::Catch => "`catch`",
+            Token::Comment => "comment",
+            Token::Ident => "identifier",
+            Token::Str => "string",
+            Token::Num => "number",
+            Token::Error => "invalid token",
+        };
+        f.write_str(text)
     }
 }
 
@@ -117,6 +263,16 @@ impl fmt::Display for Token {
 mod tests {
     use super::*;
 
+    #[test]
+    fn keywords() {
+        for keyword in KEYWORDS {
+            let mut lex = Lexer::new(keyword);
+            let token = lex.next().unwrap();
+            assert_eq!(token.to_string(), format!("`{}`", keyword));
+            assert_eq!(lex.next(), None);
+        }
+    }
+
     #[test]
     fn it_works() {
         let program = r"
@@ -145,4 +301,33 @@ mod tests {
 
         assert_eq!(lex.next(), None);
     }
+
+    #[test]
+    fn strings() {
+        let mut lex = Lexer::new(r#""a \" ${f("}")} b" 1 "open"#);
+        assert_eq!(lex.next(), Some(Token::Str));
+        assert_eq!(lex.slice(), r#""a \" ${f("}")} b""#);
+        assert_eq!(lex.next(), Some(Token::Num));
+        assert_eq!(lex.next(), Some(Token::Error));
+    }
+
+    #[test]
+    fn spans() {
+        let mut lex = Lexer::new("let foo");
+
+        assert_eq!(lex.peek(), Some(&Token::Let));
+        assert_eq!(lex.peek_span(), Some(0..3));
+        assert_eq!(lex.peek_nth(1), Some(Token::Ident));
+        assert_eq!(lex.peek_nth(2), None);
+
+        assert_eq!(lex.next(), Some(Token::Let));
+        assert_eq!(lex.peek(), Some(&Token::Ident));
+        // peeking doesn't move the span of the current token
+        assert_eq!(lex.span(), 0..3);
+        assert_eq!(lex.slice(), "let");
+
+        assert_eq!(lex.next(), Some(Token::Ident));
+        assert_eq!(lex.span(), 4..7);
+        assert_eq!(lex.peek_span(), None);
+    }
 }
@@ -3,7 +3,19 @@
 #![feature(decl_macro)]
 
 pub mod ast;
+pub mod audit;
+pub mod diagnostic;
+pub mod environment;
+pub mod helper;
 pub mod interpret;
 pub mod lex;
+pub mod meta;
 pub mod parse;
+pub mod policy;
 pub mod pratt;
+pub mod resolve;
+pub mod shell;
+pub mod stdlib;
+pub mod strip;
+#[cfg(test)]
+mod testing;
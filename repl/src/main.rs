@@ -1,43 +1,302 @@
-use interpreter::parse::Parser;
-use rustyline::validate::{
-    MatchingBracketValidator, ValidationContext, ValidationResult, Validator,
-};
+use interpreter::ast::{Stmt, Value};
+use interpreter::audit::AuditLog;
+use interpreter::diagnostic;
+use interpreter::helper::ReplHelper;
+use interpreter::interpret::{ErrorKind, Interpreter, Options, RuntimeError};
+use interpreter::lex::Lexer;
+use interpreter::meta::{MetaCommand, ShellCommand, HELP};
+use interpreter::parse::{ParseErrors, Parser};
+use interpreter::policy::{self, Policy};
+use interpreter::resolve::ResolveError;
 use rustyline::{error::ReadlineError, Editor};
-use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+use structopt::clap::AppSettings;
+use structopt::StructOpt;
 
-#[derive(Completer, Helper, Highlighter, Hinter)]
-struct InputValidator {
-    brackets: MatchingBracketValidator,
+// exit codes, from sysexits.h where one fits
+const EXIT_EXCEPTION: i32 = 1;
+const EXIT_PARSE: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_RUNTIME: i32 = 70;
+const EXIT_CONFIG: i32 = 78;
+
+#[derive(StructOpt)]
+#[structopt(name = "interpreter")]
+struct Opt {
+    /// Don't run executable comments and drop every value derived from them
+    #[structopt(long, global = true)]
+    without_comments: bool,
+
+    /// Print the commands of executable comments instead of running them
+    #[structopt(long, global = true)]
+    dry_run: bool,
+
+    /// Append a JSON line for every command run to this file
+    #[structopt(long, global = true, value_name = "file")]
+    audit_log: Option<PathBuf>,
+
+    /// Run every command in one shell, so `cd` and `export` carry over
+    #[structopt(long, global = true)]
+    shell_session: bool,
+
+    /// Only let commands run these programs
+    #[structopt(long, global = true, value_name = "programs")]
+    #[structopt(use_delimiter = true, number_of_values = 1)]
+    allow: Option<Vec<String>>,
+
+    /// Never let commands run these programs
+    #[structopt(long, global = true, value_name = "programs")]
+    #[structopt(use_delimiter = true, number_of_values = 1)]
+    deny: Vec<String>,
+
+    /// Only let commands run programs that just read, without writing files
+    #[structopt(long, global = true)]
+    read_only: bool,
+
+    /// Ask before running each command
+    #[structopt(long, global = true)]
+    confirm: bool,
+
+    /// Kill commands that run for longer than this
+    #[structopt(long, global = true, value_name = "seconds", parse(try_from_str = seconds))]
+    timeout: Option<Duration>,
+
+    /// Only pass these environment variables to commands
+    #[structopt(long, global = true, value_name = "names")]
+    #[structopt(use_delimiter = true, number_of_values = 1)]
+    keep_env: Option<Vec<String>>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-impl Validator for InputValidator {
-    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
-        self.brackets.validate(ctx)
-    }
+#[derive(StructOpt)]
+enum Command {
+    /// Run a script, passing it the arguments after the file
+    #[structopt(setting = AppSettings::TrailingVarArg)]
+    #[structopt(setting = AppSettings::AllowLeadingHyphen)]
+    Run { file: PathBuf, args: Vec<String> },
+    /// Start the interactive mode, the default without a command
+    Repl,
+    /// Print the syntax tree of a script
+    Parse { file: PathBuf },
+    /// Print the tokens of a script
+    Tokens { file: PathBuf },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let h = InputValidator {
-        brackets: MatchingBracketValidator::new(),
+    let opt = Opt::from_args();
+    let options = Options {
+        without_comments: opt.without_comments,
+        policy: policy(&opt),
+        dry_run: opt.dry_run,
+        audit: opt.audit_log.as_ref().map(AuditLog::new),
+        shell_session: opt.shell_session,
+        ..Options::default()
+    };
+
+    match opt.command.unwrap_or(Command::Repl) {
+        Command::Run { file, args } => {
+            let script = Script::open(&file);
+            let statements = script.parse_or_exit();
+            let audit = options.audit.clone().map(|audit| audit.script(&file));
+            let mut interpreter = Interpreter::with_options(Options {
+                args,
+                audit,
+                ..options
+            });
+            if let Err(error) = interpreter.interpret(&statements) {
+                eprintln!("{}", render_runtime_error(&script.source, &error));
+                process::exit(match error.kind {
+                    // found before anything ran, like syntax errors
+                    ErrorKind::Resolve(_) => EXIT_PARSE,
+                    ErrorKind::Thrown(_) => EXIT_EXCEPTION,
+                    _ => EXIT_RUNTIME,
+                });
+            }
+        }
+        Command::Repl => repl(options)?,
+        Command::Parse { file } => {
+            for statement in Script::open(&file).parse_or_exit() {
+                println!("{:#?}", statement);
+            }
+        }
+        Command::Tokens { file } => {
+            let script = Script::open(&file);
+            print_tokens(&script.source, script.body_start);
+        }
+    }
+    Ok(())
+}
+
+fn seconds(text: &str) -> Result<Duration, String> {
+    let error = || format!("`{}` is not a positive number of seconds", text);
+    match text.parse::<f64>() {
+        Ok(seconds) if seconds > 0f64 => Duration::try_from_secs_f64(seconds).map_err(|_| error()),
+        _ => Err(error()),
+    }
+}
+
+// The policy of the project's config file, if there is one, with the flags
+// on top of it. Exits if the config file is invalid.
+fn policy(opt: &Opt) -> Policy {
+    let mut policy = match policy::find_config() {
+        Some(path) => std::fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|config| Policy::parse(&config).map_err(|error| error.to_string()))
+            .unwrap_or_else(|error| {
+                eprintln!("error: {}: {}", path.display(), error);
+                process::exit(EXIT_CONFIG);
+            }),
+        None => Policy::default(),
+    };
+
+    if let Some(allow) = &opt.allow {
+        policy.allow = Some(allow.clone());
+    }
+    policy.deny.extend(opt.deny.iter().cloned());
+    policy.read_only |= opt.read_only;
+    policy.confirm |= opt.confirm;
+    if opt.timeout.is_some() {
+        policy.timeout = opt.timeout;
+    }
+    if let Some(names) = &opt.keep_env {
+        policy.keep_env = Some(names.clone());
+    }
+    policy
+}
+
+struct Script {
+    source: String,
+    // where the code starts, after a `#!` line
+    body_start: usize,
+}
+
+impl Script {
+    fn read(path: &Path) -> io::Result<Self> {
+        let source = std::fs::read_to_string(path)?;
+        let body_start = if source.starts_with("#!") {
+            source.find('\n').map_or(source.len(), |index| index + 1)
+        } else {
+            0
+        };
+        Ok(Self { source, body_start })
+    }
+
+    // exits if the file can't be read
+    fn open(path: &Path) -> Self {
+        Self::read(path).unwrap_or_else(|error| {
+            eprintln!("error: can't read {}: {}", path.display(), error);
+            process::exit(EXIT_NO_INPUT);
+        })
+    }
+
+    fn body(&self) -> &str {
+        &self.source[self.body_start..]
+    }
+
+    fn parse(&self) -> Result<Vec<Stmt>, ParseErrors> {
+        Parser::with_offset(self.body(), self.body_start).parse()
+    }
+
+    // exits after reporting every syntax error if the script doesn't parse
+    fn parse_or_exit(&self) -> Vec<Stmt> {
+        self.parse().unwrap_or_else(|errors| {
+            report_parse_errors(&self.source, &errors);
+            process::exit(EXIT_PARSE);
+        })
+    }
+}
+
+fn report_parse_errors(source: &str, errors: &ParseErrors) {
+    for error in &errors.errors {
+        eprintln!("{}", diagnostic::render(source, error.span(), error));
+    }
+}
+
+fn render_runtime_error(source: &str, error: &RuntimeError) -> String {
+    if let ErrorKind::Resolve(errors) = &error.kind {
+        return errors
+            .iter()
+            .map(|error| render_resolve_error(source, error))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+
+    let mut rendered = match &error.span {
+        Some(span) => diagnostic::render(source, span, error),
+        None => format!("error: {}", error),
     };
+    if let ErrorKind::AssignToConstant(name, declared) = &error.kind {
+        rendered.push('\n');
+        rendered.push_str(&diagnostic::note(
+            source,
+            declared,
+            format!("`{}` is declared here", name),
+        ));
+    }
+    rendered
+}
+
+fn render_resolve_error(source: &str, error: &ResolveError) -> String {
+    let mut rendered = diagnostic::render(source, error.span(), error);
+    if let ResolveError::AssignToConstant(name, declared, _) = error {
+        rendered.push('\n');
+        rendered.push_str(&diagnostic::note(
+            source,
+            declared,
+            format!("`{}` is declared here", name),
+        ));
+    }
+    rendered
+}
 
-    let mut rl = Editor::new();
-    rl.set_helper(Some(h));
+fn print_tokens(source: &str, start: usize) {
+    let mut lexer = Lexer::new(&source[start..]);
+    while let Some(token) = lexer.next() {
+        let (line, column) = diagnostic::line_col(source, start + lexer.span().start);
+        println!("{}:{}\t{:?}\t{:?}", line, column, token, lexer.slice());
+    }
+}
+
+fn repl(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(ReplHelper::new()));
     if rl.load_history("history.txt").is_err() {
         std::fs::write("history.txt", "")?;
     }
 
+    let mut interpreter = Interpreter::with_options(options.clone());
+
+    // every line entered so far, so spans keep pointing at the right line
+    let mut session = String::new();
+
     loop {
         let readline = rl.readline("> ");
         match readline {
             Ok(line) => {
-                if !line.trim().is_empty() {
-                    rl.add_history_entry(line.as_str());
+                if line.trim().is_empty() {
+                    continue;
+                }
 
-                    let mut parser = Parser::new(&line);
-                    let ast = parser.parse()?;
+                match MetaCommand::parse(&line) {
+                    Some(Ok(command)) => {
+                        run_meta(command, &mut interpreter, &mut session, &options)
+                    }
+                    Some(Err(error)) => eprintln!("error: {}", error),
+                    None => {
+                        rl.add_history_entry(line.as_str());
+                        if let Some(value) = eval(&mut interpreter, &mut session, &line) {
+                            println!("{:?}", value);
+                        }
+                    }
+                }
 
-                    println!("{:#?}", ast);
+                if let Some(helper) = rl.helper_mut() {
+                    helper.refresh(&interpreter);
                 }
             }
             Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
@@ -51,3 +310,104 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     rl.save_history("history.txt")?;
     Ok(())
 }
+
+// runs some code entered in the session, reporting its errors
+fn eval(interpreter: &mut Interpreter, session: &mut String, code: &str) -> Option<Value> {
+    let offset = session.len();
+    session.push_str(code);
+    session.push('\n');
+
+    let ast = match Parser::with_offset(code, offset).parse() {
+        Ok(ast) => ast,
+        Err(errors) => {
+            report_parse_errors(session, &errors);
+            return None;
+        }
+    };
+
+    match interpreter.interpret(&ast) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            eprintln!("{}", render_runtime_error(session, &error));
+            None
+        }
+    }
+}
+
+fn run_meta(
+    command: MetaCommand,
+    interpreter: &mut Interpreter,
+    session: &mut String,
+    options: &Options,
+) {
+    match command {
+        MetaCommand::Ast(code) => match Parser::new(&code).parse() {
+            Ok(statements) => {
+                for statement in statements {
+                    println!("{:#?}", statement);
+                }
+            }
+            Err(errors) => report_parse_errors(&code, &errors),
+        },
+        MetaCommand::Tokens(code) => print_tokens(&code, 0),
+        MetaCommand::Load(path) => {
+            let script = match Script::read(&path) {
+                Ok(script) => script,
+                Err(error) => {
+                    eprintln!("error: can't read {}: {}", path.display(), error);
+                    return;
+                }
+            };
+            match script.parse() {
+                Ok(statements) => {
+                    if let Err(error) = interpreter.interpret(&statements) {
+                        eprintln!("{}", render_runtime_error(&script.source, &error));
+                    }
+                }
+                Err(errors) => report_parse_errors(&script.source, &errors),
+            }
+        }
+        MetaCommand::Env => {
+            for (name, binding) in interpreter.globals() {
+                match &binding.value {
+                    // a function shown the way it was declared
+                    Value::Function(function) if function.name.as_ref() == Some(&name) => {
+                        println!("fn {}({})", name, function.params.join(", "));
+                    }
+                    value => {
+                        let keyword = if binding.constant { "const" } else { "let" };
+                        println!("{} {} = {:?}", keyword, name, value);
+                    }
+                }
+            }
+        }
+        MetaCommand::Reset => {
+            *interpreter = Interpreter::with_options(options.clone());
+            session.clear();
+        }
+        MetaCommand::Type(code) => {
+            if let Some(value) = eval(interpreter, session, &code) {
+                println!("{}", value.type_name());
+            }
+        }
+        MetaCommand::Time(code) => {
+            let start = Instant::now();
+            if let Some(value) = eval(interpreter, session, &code) {
+                println!("{:?}", value);
+            }
+            println!("took {:?}", start.elapsed());
+        }
+        MetaCommand::Shell(ShellCommand::Restart) => {
+            if options.shell_session {
+                interpreter.restart_shell();
+            } else {
+                println!("every command starts its own shell without --shell-session");
+            }
+        }
+        MetaCommand::Shell(ShellCommand::Cwd) => match interpreter.shell_cwd() {
+            Ok(cwd) => println!("{}", cwd.display()),
+            Err(error) => eprintln!("error: can't get the shell's directory: {}", error),
+        },
+        MetaCommand::Help => println!("{}", HELP),
+    }
+}
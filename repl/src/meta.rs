@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum MetaError {
+    #[error("unknown command `:{0}`, try `:help`")]
+    UnknownCommand(String),
+
+    #[error("`:{0}` needs {1}")]
+    MissingArgument(&'static str, &'static str),
+
+    #[error("`:{0}` doesn't know `{1}`, it needs {2}")]
+    InvalidArgument(&'static str, String, &'static str),
+}
+
+/// The REPL's own commands, written with a leading `:` to set them apart from
+/// code.
+#[derive(Debug, PartialEq)]
+pub enum MetaCommand {
+    Ast(String),
+    Tokens(String),
+    Load(PathBuf),
+    Env,
+    Reset,
+    Type(String),
+    Time(String),
+    Shell(ShellCommand),
+    Help,
+}
+
+/// What `:shell` does to the shell commands run in.
+#[derive(Debug, PartialEq)]
+pub enum ShellCommand {
+    Restart,
+    Cwd,
+}
+
+pub const HELP: &str = "\
+:ast <code>     print the syntax tree of some code
+:tokens <code>  print the tokens of some code
+:load <file>    run a script in this session
+:env            list the session's bindings and functions
+:reset          forget everything defined in this session
+:type <expr>    print the type of an expression's value
+:time <code>    run some code and print how long it took
+:shell restart  start a new shell for commands to run in
+:shell cwd      print the directory commands run in
+:help           print this help";
+
+impl MetaCommand {
+    /// Reads a meta-command from a line of input, `None` if it's code.
+    pub fn parse(line: &str) -> Option<Result<Self, MetaError>> {
+        let line = line.trim().strip_prefix(':')?;
+        let (name, argument) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+
+        let code = |name| match argument {
+            "" => Err(MetaError::MissingArgument(name, "some code")),
+            code => Ok(code.to_string()),
+        };
+        Some(match name {
+            "ast" => code("ast").map(Self::Ast),
+            "tokens" => code("tokens").map(Self::Tokens),
+            "load" => match argument {
+                "" => Err(MetaError::MissingArgument("load", "a file")),
+                file => Ok(Self::Load(PathBuf::from(file))),
+            },
+            "env" => Ok(Self::Env),
+            "reset" => Ok(Self::Reset),
+            "type" => code("type").map(Self::Type),
+            "time" => code("time").map(Self::Time),
+            "shell" => match argument {
+                "restart" => Ok(Self::Shell(ShellCommand::Restart)),
+                "cwd" => Ok(Self::Shell(ShellCommand::Cwd)),
+                "" => Err(MetaError::MissingArgument("shell", "`restart` or `cwd`")),
+                other => Err(MetaError::InvalidArgument(
+                    "shell",
+                    other.to_string(),
+                    "`restart` or `cwd`",
+                )),
+            },
+            "help" => Ok(Self::Help),
+            name => Err(MetaError::UnknownCommand(name.to_string())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(MetaCommand::parse("let x = 1"), None);
+        assert_eq!(MetaCommand::parse(" :env "), Some(Ok(MetaCommand::Env)));
+        assert_eq!(
+            MetaCommand::parse(":ast 1 +  2"),
+            Some(Ok(MetaCommand::Ast("1 +  2".to_string())))
+        );
+        assert_eq!(
+            MetaCommand::parse(":load examples/if-else.repl"),
+            Some(Ok(MetaCommand::Load(PathBuf::from(
+                "examples/if-else.repl"
+            ))))
+        );
+        assert_eq!(
+            MetaCommand::parse(":type"),
+            Some(Err(MetaError::MissingArgument("type", "some code")))
+        );
+        assert_eq!(
+            MetaCommand::parse(":shell cwd"),
+            Some(Ok(MetaCommand::Shell(ShellCommand::Cwd)))
+        );
+        assert_eq!(
+            MetaCommand::parse(":shell stop"),
+            Some(Err(MetaError::InvalidArgument(
+                "shell",
+                "stop".to_string(),
+                "`restart` or `cwd`"
+            )))
+        );
+        assert_eq!(
+            MetaCommand::parse(":quit"),
+            Some(Err(MetaError::UnknownCommand("quit".to_string())))
+        );
+    }
+}
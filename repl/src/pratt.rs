@@ -13,7 +13,7 @@ pub enum Precedence {
     Term,       // + -
     Factor,     // * /
     Unary,      // ! -
-    Call,       // . ()
+    Call,       // . () []
     Primary,
 }
 
@@ -26,9 +26,16 @@ pub enum ParseFn {
 
     Literal,
     Variable,
+    Function,
+    List,
+    Map,
 
     And,
     Or,
+
+    Call,
+    Dot,
+    Index,
 }
 
 pub struct ParseRule {
@@ -59,9 +66,24 @@ pub fn get_rule(operator: &Token) -> ParseRule {
     match operator {
         Token::LeftParen => ParseRule {
             prefix: ParseFn::Grouping,
+            infix: ParseFn::Call,
+            precedence: Precedence::Call,
+        },
+        Token::LeftBracket => ParseRule {
+            prefix: ParseFn::List,
+            infix: ParseFn::Index,
+            precedence: Precedence::Call,
+        },
+        Token::LeftBrace => ParseRule {
+            prefix: ParseFn::Map,
             infix: ParseFn::None,
             precedence: Precedence::None,
         },
+        Token::Dot => ParseRule {
+            prefix: ParseFn::None,
+            infix: ParseFn::Dot,
+            precedence: Precedence::Call,
+        },
         Token::Minus => ParseRule {
             prefix: ParseFn::Unary,
             infix: ParseFn::Binary,
@@ -92,8 +114,15 @@ pub fn get_rule(operator: &Token) -> ParseRule {
             infix: ParseFn::Binary,
             precedence: Precedence::Comparison,
         },
-        Token::True | Token::False | Token::Num | Token::Str | Token::Null => ParseRule {
-            prefix: ParseFn::Literal,
+        Token::True | Token::False | Token::Num | Token::Str | Token::Null | Token::Comment => {
+            ParseRule {
+                prefix: ParseFn::Literal,
+                infix: ParseFn::None,
+                precedence: Precedence::None,
+            }
+        }
+        Token::Func => ParseRule {
+            prefix: ParseFn::Function,
             infix: ParseFn::None,
             precedence: Precedence::None,
         },
@@ -0,0 +1,537 @@
+use crate::ast::{Expr, ExprKind, Slot, Span, Stmt, StmtKind};
+use crate::environment::Environment;
+use crate::stdlib;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    #[error("`return` outside of a function")]
+    ReturnOutsideFunction(Span),
+
+    #[error("`break` outside of a loop")]
+    BreakOutsideLoop(Span),
+
+    #[error("`continue` outside of a loop")]
+    ContinueOutsideLoop(Span),
+
+    #[error("unknown loop label `{0}`")]
+    UnknownLabel(String, Span),
+
+    #[error("undefined variable `{0}`")]
+    UndefinedVariable(String, Span),
+
+    #[error("undefined function `{0}`")]
+    UndefinedFunction(String, Span),
+
+    #[error("cannot assign to `{0}`, it is a constant")]
+    AssignToConstant(String, Span, Span), // name, where it was declared
+
+    #[error("parameter `{0}` is declared more than once")]
+    DuplicateParameter(String, Span),
+}
+
+impl ResolveError {
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::ReturnOutsideFunction(span)
+            | Self::BreakOutsideLoop(span)
+            | Self::ContinueOutsideLoop(span)
+            | Self::UnknownLabel(_, span)
+            | Self::UndefinedVariable(_, span)
+            | Self::UndefinedFunction(_, span)
+            | Self::AssignToConstant(_, _, span)
+            | Self::DuplicateParameter(_, span) => span,
+        }
+    }
+}
+
+/// Checks what can be known about a program before running it, given the
+/// global scope it will run in, and tells every local variable and
+/// assignment which slot it refers to.
+pub fn resolve(statements: &[Stmt], globals: &Environment) -> Result<(), Vec<ResolveError>> {
+    let mut resolver = Resolver {
+        globals,
+        declared: HashMap::new(),
+        hoisted: statements.iter().filter_map(declared_name).collect(),
+        scopes: Vec::new(),
+        loops: Vec::new(),
+        in_function: false,
+        errors: Vec::new(),
+    };
+    resolver.statements(statements);
+
+    if resolver.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(resolver.errors)
+    }
+}
+
+/// Resolves an expression about to be run in `environment`, like one
+/// embedded in a comment's command, against the scopes around it as they are
+/// while running.
+pub fn resolve_running(
+    expr: &Expr,
+    environment: &Environment,
+    globals: &Environment,
+) -> Result<(), Vec<ResolveError>> {
+    let mut scopes = Vec::new();
+    let mut enclosing = environment.enclosing();
+    if enclosing.is_some() {
+        scopes.push(Scope::from(environment));
+    }
+    while let Some(scope) = enclosing {
+        let scope = scope.borrow();
+        enclosing = scope.enclosing();
+        if enclosing.is_some() {
+            scopes.push(Scope::from(&*scope));
+        }
+    }
+    scopes.reverse();
+
+    let mut resolver = Resolver {
+        globals,
+        declared: HashMap::new(),
+        hoisted: HashSet::new(),
+        scopes,
+        loops: Vec::new(),
+        in_function: false,
+        errors: Vec::new(),
+    };
+    resolver.expr(expr);
+
+    if resolver.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(resolver.errors)
+    }
+}
+
+fn declared_name(statement: &Stmt) -> Option<String> {
+    match &statement.kind {
+        StmtKind::VariableDeclaration { name, .. }
+        | StmtKind::ConstDeclaration { name, .. }
+        | StmtKind::CommentDeclaration { name, .. }
+        | StmtKind::FnDeclaration { name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+// what the resolver knows of a binding
+#[derive(Clone)]
+struct Declaration {
+    constant: bool,
+    span: Span,
+}
+
+// a local scope, laid out the way its `Environment` will be
+#[derive(Default)]
+struct Scope {
+    slots: Vec<Declaration>,
+    names: HashMap<String, usize>,
+}
+
+// the scope an `Environment` that is already running was laid out from
+impl From<&Environment> for Scope {
+    fn from(environment: &Environment) -> Self {
+        let slots = environment
+            .slots()
+            .iter()
+            .map(|binding| Declaration {
+                constant: binding.constant,
+                span: binding.declared.clone(),
+            })
+            .collect();
+        Self {
+            slots,
+            names: environment.names().clone(),
+        }
+    }
+}
+
+struct Resolver<'g> {
+    globals: &'g Environment,
+    // globals the program has declared so far
+    declared: HashMap<String, Declaration>,
+    // every global the program declares, which function bodies can use
+    // before the declaration has run
+    hoisted: HashSet<String>,
+    // local scopes around the code being resolved, innermost last
+    scopes: Vec<Scope>,
+    // enclosing loops of the current function, innermost last
+    loops: Vec<Option<String>>,
+    in_function: bool,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver<'_> {
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        let span = &statement.span;
+        match &statement.kind {
+            StmtKind::VariableDeclaration { name, value } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                self.declare(name, false, span);
+            }
+            StmtKind::ConstDeclaration { name, value } => {
+                self.expr(value);
+                self.declare(name, true, span);
+            }
+            StmtKind::CommentDeclaration { name, value } => {
+                self.expr(value);
+                self.declare(name, false, span);
+            }
+            // declared first, so the function can call itself
+            StmtKind::FnDeclaration {
+                name, params, body, ..
+            } => {
+                self.declare(name, true, span);
+                self.function(params, body, span);
+            }
+            StmtKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.expr(condition);
+                self.statement(then);
+                if let Some(otherwise) = otherwise {
+                    self.statement(otherwise);
+                }
+            }
+            StmtKind::Loop { label, body } => {
+                self.loops.push(label.clone());
+                self.statement(body);
+                self.loops.pop();
+            }
+            StmtKind::For {
+                name,
+                iterable,
+                body,
+            } => {
+                self.expr(iterable);
+                self.loops.push(None);
+                self.scopes.push(Scope::default());
+                self.declare(name, false, span);
+                self.statement(body);
+                self.scopes.pop();
+                self.loops.pop();
+            }
+            StmtKind::Block(statements) => {
+                self.scopes.push(Scope::default());
+                self.statements(statements);
+                self.scopes.pop();
+            }
+            StmtKind::Try {
+                body,
+                name,
+                handler,
+            } => {
+                self.statement(body);
+                self.scopes.push(Scope::default());
+                if let Some(name) = name {
+                    self.declare(name, false, span);
+                }
+                self.statement(handler);
+                self.scopes.pop();
+            }
+            StmtKind::Return(value) => {
+                if !self.in_function {
+                    self.errors
+                        .push(ResolveError::ReturnOutsideFunction(span.clone()));
+                }
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            StmtKind::Break(label) => {
+                self.jump(label, ResolveError::BreakOutsideLoop(span.clone()), span)
+            }
+            StmtKind::Continue(label) => {
+                self.jump(label, ResolveError::ContinueOutsideLoop(span.clone()), span)
+            }
+            StmtKind::Throw(value) | StmtKind::Expr(value) => self.expr(value),
+            StmtKind::Comment(_) => {}
+        }
+    }
+
+    // Functions see the scopes around them, but loops don't reach into them.
+    fn function(&mut self, params: &[String], body: &Stmt, span: &Span) {
+        let loops = std::mem::take(&mut self.loops);
+        let in_function = std::mem::replace(&mut self.in_function, true);
+        self.scopes.push(Scope::default());
+
+        for (index, param) in params.iter().enumerate() {
+            if params[..index].contains(param) {
+                let error = ResolveError::DuplicateParameter(param.clone(), span.clone());
+                self.errors.push(error);
+            }
+            self.declare(param, false, span);
+        }
+        self.statement(body);
+
+        self.scopes.pop();
+        self.loops = loops;
+        self.in_function = in_function;
+    }
+
+    fn jump(&mut self, label: &Option<String>, outside: ResolveError, span: &Span) {
+        match label {
+            _ if self.loops.is_empty() => self.errors.push(outside),
+            Some(label) if !self.loops.contains(&Some(label.clone())) => {
+                let error = ResolveError::UnknownLabel(label.clone(), span.clone());
+                self.errors.push(error);
+            }
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Unary { expr, .. } | ExprKind::Grouping(expr) => self.expr(expr),
+            ExprKind::Literal(_) => {}
+            ExprKind::Variable(name) => {
+                if !self.lookup(name, expr) {
+                    let error = ResolveError::UndefinedVariable(name.clone(), expr.span.clone());
+                    self.errors.push(error);
+                }
+            }
+            ExprKind::Assignment(name, value) => {
+                self.expr(value);
+                if !self.lookup(name, expr) {
+                    let error = ResolveError::UndefinedVariable(name.clone(), expr.span.clone());
+                    self.errors.push(error);
+                    return;
+                }
+                if let Some(declaration) = self.declaration(name, expr) {
+                    if declaration.constant {
+                        self.errors.push(ResolveError::AssignToConstant(
+                            name.clone(),
+                            declaration.span,
+                            expr.span.clone(),
+                        ));
+                    }
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                // a name that isn't a variable is the name of a function
+                match &callee.kind {
+                    ExprKind::Variable(name) => {
+                        if !self.lookup(name, callee) && stdlib::lookup(name).is_none() {
+                            let error =
+                                ResolveError::UndefinedFunction(name.clone(), callee.span.clone());
+                            self.errors.push(error);
+                        }
+                    }
+                    _ => self.expr(callee),
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Get { object, .. } => self.expr(object),
+            ExprKind::List(items) | ExprKind::Interpolation(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expr(object);
+                self.expr(index);
+            }
+            ExprKind::IndexAssignment {
+                object,
+                index,
+                value,
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            ExprKind::Function { params, body } => self.function(params, body, &expr.span),
+        }
+    }
+
+    fn declare(&mut self, name: &str, constant: bool, span: &Span) {
+        let declaration = Declaration {
+            constant,
+            span: span.clone(),
+        };
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.names.insert(name.to_string(), scope.slots.len());
+                scope.slots.push(declaration);
+            }
+            None => {
+                self.declared.insert(name.to_string(), declaration);
+            }
+        }
+    }
+
+    // Gives `expr` the slot of the local `name` refers to. Whether `name` is
+    // declared at all.
+    fn lookup(&self, name: &str, expr: &Expr) -> bool {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&index) = scope.names.get(name) {
+                expr.slot.set(Some(Slot { depth, index }));
+                return true;
+            }
+        }
+        expr.slot.set(None);
+
+        self.declared.contains_key(name)
+            || self.globals.binding(name).is_some()
+            || (self.in_function && self.hoisted.contains(name))
+    }
+
+    // what is known of the binding `expr` was resolved to
+    fn declaration(&self, name: &str, expr: &Expr) -> Option<Declaration> {
+        match expr.slot.get() {
+            Some(slot) => {
+                let scope = &self.scopes[self.scopes.len() - 1 - slot.depth];
+                Some(scope.slots[slot.index].clone())
+            }
+            None => self.declared.get(name).cloned().or_else(|| {
+                let binding = self.globals.binding(name)?;
+                Some(Declaration {
+                    constant: binding.constant,
+                    span: binding.declared.clone(),
+                })
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Parser;
+
+    fn check(program: &str) -> Vec<String> {
+        let statements = Parser::new(program).parse().unwrap();
+        match resolve(&statements, &Environment::default()) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn jumps() {
+        assert!(check("loop outer { loop { break outer } }").is_empty());
+        assert!(check("loop { continue }").is_empty());
+        assert!(check("loop outer { for x in [] { break outer } }").is_empty());
+        assert_eq!(check("break"), ["`break` outside of a loop"]);
+        assert_eq!(check("continue"), ["`continue` outside of a loop"]);
+        assert_eq!(
+            check("loop inner {} loop { break inner }"),
+            ["unknown loop label `inner`"]
+        );
+        assert_eq!(
+            check("loop { fn f() { break } }"),
+            ["`break` outside of a loop"]
+        );
+    }
+
+    #[test]
+    fn returns() {
+        assert!(check("fn f() { loop { return 1 } }").is_empty());
+        assert_eq!(check("return"), ["`return` outside of a function"]);
+        assert_eq!(
+            check("if true { return 1 }"),
+            ["`return` outside of a function"]
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert!(check("let a = 1 { let b = a b = 2 }").is_empty());
+        assert!(check("fn f() { later } let later = 1").is_empty());
+        assert!(check("fn f(a) { g(a) } fn g(a) {}").is_empty());
+        assert!(check("print(len([]))").is_empty());
+        assert_eq!(
+            check("undefined_fn(1)"),
+            ["undefined function `undefined_fn`"]
+        );
+        assert_eq!(check("fn f() { g() }"), ["undefined function `g`"]);
+        assert_eq!(check("a"), ["undefined variable `a`"]);
+        assert_eq!(check("a = 1 let a"), ["undefined variable `a`"]);
+        assert_eq!(check("{ let b = 1 } b"), ["undefined variable `b`"]);
+        assert!(check("{ let c = 1 fn f() { c } }").is_empty());
+        assert!(check("for x in [] { x }").is_empty());
+        assert_eq!(check("for x in [] {} x"), ["undefined variable `x`"]);
+        assert!(check("let f = fn (n) { f(n) }").is_empty());
+        assert_eq!(
+            check("fn f() { let c = 1 } fn g() { c }"),
+            ["undefined variable `c`"]
+        );
+        assert_eq!(
+            check("loop { let f = fn () { break } }"),
+            ["`break` outside of a loop"]
+        );
+        assert_eq!(
+            check("const a = 1 { a = 2 } fn f(a, b, a) { a = 3 }"),
+            [
+                "cannot assign to `a`, it is a constant",
+                "parameter `a` is declared more than once",
+            ]
+        );
+
+        // every error is reported with where it happened
+        let statements = Parser::new("let x = y\nreturn x").parse().unwrap();
+        let spans: Vec<Span> = resolve(&statements, &Environment::default())
+            .unwrap_err()
+            .iter()
+            .map(|error| error.span().clone())
+            .collect();
+        assert_eq!(spans, [8..9, 10..18]);
+    }
+
+    #[test]
+    fn slots() {
+        let program = "let g = 1 { let a = 1 let b = 2 { let a = 3 b a g } }";
+        let statements = Parser::new(program).parse().unwrap();
+        resolve(&statements, &Environment::default()).unwrap();
+
+        let outer = match &statements[1].kind {
+            StmtKind::Block(statements) => statements,
+            kind => panic!("expected a block, got {:?}", kind),
+        };
+        let inner = match &outer[2].kind {
+            StmtKind::Block(statements) => statements,
+            kind => panic!("expected a block, got {:?}", kind),
+        };
+        let slots: Vec<Option<Slot>> = inner[1..]
+            .iter()
+            .map(|statement| match &statement.kind {
+                StmtKind::Expr(expr) => expr.slot.get(),
+                kind => panic!("expected an expression, got {:?}", kind),
+            })
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                Some(Slot { depth: 1, index: 1 }),
+                Some(Slot { depth: 0, index: 0 }),
+                None,
+            ]
+        );
+    }
+}
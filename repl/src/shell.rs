@@ -0,0 +1,547 @@
+use crate::policy::Policy;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs `command` through the system shell, streaming its stdout and stderr
+/// to ours. The policy's timeout and environment apply, checking whether it
+/// may run at all is left to `Policy::permit`.
+pub fn run(command: &str, policy: &Policy) -> io::Result<ExitStatus> {
+    let mut child = shell(command, policy).spawn()?;
+    wait(&mut child, policy, &[])
+}
+
+/// What a command wrote and how it exited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` if the command was killed by a signal.
+    pub status: Option<i32>,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// Runs `command` like `run`, capturing its stdout and stderr with trailing
+/// newlines removed.
+pub fn capture(command: &str, policy: &Policy) -> io::Result<Output> {
+    let mut child = shell(command, policy)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // both are read while it runs, so neither pipe fills up and blocks it
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+    let status = wait(&mut child, policy, &[&stdout, &stderr])?;
+
+    let text = |reader: thread::JoinHandle<Vec<u8>>| {
+        let bytes = reader.join().unwrap_or_default();
+        let text = String::from_utf8_lossy(&bytes);
+        text.trim_end_matches(&['\n', '\r'][..]).to_string()
+    };
+    Ok(Output {
+        stdout: text(stdout),
+        stderr: text(stderr),
+        status: status.code(),
+    })
+}
+
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+// Waits for a command to exit and for `pipes` to be read to their end,
+// which takes as long as anything it started keeps them open. Once it runs
+// out of time, it's killed along with everything it started.
+fn wait(
+    child: &mut Child,
+    policy: &Policy,
+    pipes: &[&thread::JoinHandle<Vec<u8>>],
+) -> io::Result<ExitStatus> {
+    let timeout = match policy.timeout {
+        Some(timeout) => timeout,
+        None => return child.wait(),
+    };
+
+    let start = Instant::now();
+    let mut status = None;
+    loop {
+        if status.is_none() {
+            status = child.try_wait()?;
+        }
+        match status {
+            Some(status) if pipes.iter().all(|pipe| pipe.is_finished()) => return Ok(status),
+            _ => {}
+        }
+        if start.elapsed() >= timeout {
+            kill_group(child)?;
+            child.wait()?;
+            let message = format!("it ran for longer than {:?}", timeout);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, message));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// A command that can time out runs in a process group of its own, so it can
+// be killed along with whatever it starts. Without a timeout it stays in
+// ours, where it gets the terminal's Ctrl-C and can read from it.
+#[cfg(unix)]
+fn own_group(command: &mut Command, policy: &Policy) {
+    if policy.timeout.is_some() {
+        command.process_group(0);
+    }
+}
+
+#[cfg(not(unix))]
+fn own_group(_: &mut Command, _: &Policy) {}
+
+// Kills a command started in a group of its own, and everything in it. The
+// shell's `kill` does it, since a child can only be sent a signal by itself.
+#[cfg(unix)]
+fn kill_group(child: &mut Child) -> io::Result<()> {
+    let killed = Command::new("sh")
+        .arg("-c")
+        .arg(format!("kill -s KILL -- -{}", child.id()))
+        .stderr(Stdio::null())
+        .status()?;
+    // the group is gone once everything in it has exited
+    if !killed.success() {
+        let _ = child.kill();
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill_group(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+/// One shell kept running for many commands, so what a command changes in
+/// it, like its directory or the variables it exports, carries over to the
+/// next ones. Commands read their stdin from `/dev/null`.
+///
+/// A command is followed by a line with a sentinel on stdout, along with its
+/// exit status, and another on stderr, marking where its output ends. A
+/// command that `exit`s the shell or closes its output ends the session.
+pub struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    // lines of the shell's stdout and stderr as they come, `None` once it
+    // closes them
+    lines: Receiver<(Stream, Option<Vec<u8>>)>,
+    sentinel: String,
+    // how long a command can run, the policy's when the shell started
+    timeout: Option<Duration>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stream {
+    Out,
+    Err,
+}
+
+impl Session {
+    /// Starts a shell with the policy's environment and timeout.
+    #[cfg(not(windows))]
+    pub fn start(policy: &Policy) -> io::Result<Self> {
+        let mut shell = Command::new("sh");
+        environment(&mut shell, policy);
+        own_group(&mut shell, policy);
+        let mut child = shell
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (sender, lines) = mpsc::channel();
+        read_lines(child.stdout.take(), Stream::Out, sender.clone());
+        read_lines(child.stderr.take(), Stream::Err, sender);
+        let stdin = child.stdin.take().expect("the shell's stdin is piped");
+
+        let seed = RandomState::new().build_hasher().finish();
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+            sentinel: format!("__repl_done_{:016x}", seed),
+            timeout: policy.timeout,
+        })
+    }
+
+    // the framing is written for a POSIX shell
+    #[cfg(windows)]
+    pub fn start(_: &Policy) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "shell sessions need a POSIX shell",
+        ))
+    }
+
+    /// Runs `command` in the session, capturing its output or letting it
+    /// write to ours. Once it runs out of time, the shell is killed along
+    /// with everything it started. After an error, the session can't be
+    /// used anymore.
+    pub fn run(&mut self, command: &str, capture: bool) -> io::Result<Output> {
+        let framed = format!(
+            "command eval {command} </dev/null\n\
+             printf '\\n%s %d\\n' {sentinel} \"$?\"\n\
+             printf '\\n%s\\n' {sentinel} >&2\n",
+            command = quote(command, Quotes::Bare),
+            sentinel = self.sentinel,
+        );
+        self.stdin.write_all(framed.as_bytes())?;
+        self.stdin.flush()?;
+
+        let deadline = self
+            .timeout
+            .map(|timeout| (timeout, Instant::now() + timeout));
+        let mut stdout = Framed::new(capture, Stream::Out);
+        let mut stderr = Framed::new(capture, Stream::Err);
+        let mut status = None;
+
+        // the shell is checked on between lines, since something it left
+        // running can keep its output open after it's gone
+        let tick = Duration::from_millis(50);
+        while !(stdout.done && stderr.done) {
+            let wait = match deadline {
+                Some((_, deadline)) => deadline.saturating_duration_since(Instant::now()).min(tick),
+                None => tick,
+            };
+            let (stream, line) = match self.lines.recv_timeout(wait) {
+                Ok((stream, Some(line))) => (stream, line),
+                Err(RecvTimeoutError::Timeout) => match deadline {
+                    Some((timeout, deadline)) if Instant::now() >= deadline => {
+                        self.kill();
+                        let message = format!("it ran for longer than {:?}", timeout);
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, message));
+                    }
+                    _ if self.child.try_wait()?.is_some() => return Err(self.end()),
+                    _ => continue,
+                },
+                // no sentinel can come through a closed stream
+                Ok((_, None)) | Err(RecvTimeoutError::Disconnected) => return Err(self.end()),
+            };
+
+            let framed = match stream {
+                Stream::Out => &mut stdout,
+                Stream::Err => &mut stderr,
+            };
+            match line.strip_prefix(self.sentinel.as_bytes()) {
+                Some(rest) => {
+                    if stream == Stream::Out {
+                        status = String::from_utf8_lossy(rest).trim().parse().ok();
+                    }
+                    framed.end()?;
+                }
+                None => framed.push(line)?,
+            }
+        }
+
+        Ok(Output {
+            stdout: stdout.text(),
+            stderr: stderr.text(),
+            status,
+        })
+    }
+
+    // The shell exited, or a command closed the output it answers on. Either
+    // way it's done, and is killed if it hasn't exited.
+    fn end(&mut self) -> io::Error {
+        let start = Instant::now();
+        let status = loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => break status.code(),
+                // a closed stream is often the shell exiting
+                Ok(None) if start.elapsed() < Duration::from_millis(100) => {
+                    thread::sleep(Duration::from_millis(5))
+                }
+                _ => {
+                    self.kill();
+                    break None;
+                }
+            }
+        };
+        let message = match status {
+            Some(status) => format!("the shell session ended with status {}", status),
+            None => "the shell session ended".to_string(),
+        };
+        io::Error::new(io::ErrorKind::BrokenPipe, message)
+    }
+
+    // with a timeout, the shell leads a group of its own
+    fn kill(&mut self) {
+        let _ = match self.timeout {
+            Some(_) => kill_group(&mut self.child),
+            None => self.child.kill(),
+        };
+    }
+
+    /// The directory commands run in.
+    pub fn cwd(&mut self) -> io::Result<String> {
+        self.run("pwd", true).map(|output| output.stdout)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn read_lines(
+    pipe: Option<impl Read + Send + 'static>,
+    stream: Stream,
+    lines: Sender<(Stream, Option<Vec<u8>>)>,
+) {
+    thread::spawn(move || {
+        if let Some(pipe) = pipe {
+            let mut pipe = BufReader::new(pipe);
+            loop {
+                let mut line = Vec::new();
+                match pipe.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if lines.send((stream, Some(line))).is_err() => return,
+                    Ok(_) => {}
+                }
+            }
+        }
+        let _ = lines.send((stream, None));
+    });
+}
+
+// The output of a command run in a session. The line before a sentinel
+// ends with a line break the sentinel brought, so lines are held back one
+// at a time until it's known whether they're the last.
+struct Framed {
+    capture: bool,
+    stream: Stream,
+    held: Option<Vec<u8>>,
+    captured: Vec<u8>,
+    done: bool,
+}
+
+impl Framed {
+    fn new(capture: bool, stream: Stream) -> Self {
+        Self {
+            capture,
+            stream,
+            held: None,
+            captured: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn push(&mut self, line: Vec<u8>) -> io::Result<()> {
+        match self.held.replace(line) {
+            Some(line) => self.write(&line),
+            None => Ok(()),
+        }
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.done = true;
+        match self.held.take() {
+            Some(mut line) => {
+                line.pop();
+                self.write(&line)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match (self.capture, self.stream) {
+            (true, _) => {
+                self.captured.extend_from_slice(bytes);
+                Ok(())
+            }
+            (false, Stream::Out) => io::stdout().write_all(bytes),
+            (false, Stream::Err) => io::stderr().write_all(bytes),
+        }
+    }
+
+    fn text(&self) -> String {
+        let text = String::from_utf8_lossy(&self.captured);
+        text.trim_end_matches(&['\n', '\r'][..]).to_string()
+    }
+}
+
+/// Where a value is put in a command, which decides how it's quoted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quotes {
+    Bare,
+    Single,
+    Double,
+}
+
+/// Quotes `text` so the shell reads it as plain text where it's put, and
+/// never as more of the command.
+#[cfg(not(windows))]
+pub fn quote(text: &str, quotes: Quotes) -> String {
+    match quotes {
+        Quotes::Bare => format!("'{}'", text.replace('\'', r"'\''")),
+        Quotes::Single => text.replace('\'', r"'\''"),
+        Quotes::Double => {
+            let mut quoted = String::with_capacity(text.len());
+            for c in text.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted
+        }
+    }
+}
+
+// cmd has no single quotes, and only `"` means something between double ones
+#[cfg(windows)]
+pub fn quote(text: &str, quotes: Quotes) -> String {
+    let text = text.replace('"', "\"\"");
+    match quotes {
+        Quotes::Double => text,
+        Quotes::Bare | Quotes::Single => format!("\"{}\"", text),
+    }
+}
+
+fn shell(command: &str, policy: &Policy) -> Command {
+    let mut shell = system_shell();
+    shell.arg(command);
+    environment(&mut shell, policy);
+    own_group(&mut shell, policy);
+    shell
+}
+
+fn environment(shell: &mut Command, policy: &Policy) {
+    if let Some(names) = &policy.keep_env {
+        shell.env_clear();
+        for name in names {
+            if let Some(value) = std::env::var_os(name) {
+                shell.env(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn system_shell() -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c");
+    shell
+}
+
+#[cfg(windows)]
+fn system_shell() -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C");
+    shell
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() -> Result<(), Box<dyn std::error::Error>> {
+        let text = "it's \"$HOME\" `ls` \\";
+        let commands = [
+            format!("printf %s {}", quote(text, Quotes::Bare)),
+            format!("printf %s '{}'", quote(text, Quotes::Single)),
+            format!("printf %s \"{}\"", quote(text, Quotes::Double)),
+        ];
+        for command in &commands {
+            assert_eq!(capture(command, &Policy::default())?.stdout, text);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn policy() -> Result<(), Box<dyn std::error::Error>> {
+        let policy = Policy {
+            timeout: Some(Duration::from_millis(100)),
+            keep_env: Some(vec!["PATH".to_string()]),
+            ..Policy::default()
+        };
+        let error = capture("sleep 5", &policy).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+
+        // what it started is killed with it, even once the shell has exited
+        let start = Instant::now();
+        for command in ["sleep 5 & wait", "(sleep 5)", "sleep 5 & echo started"] {
+            let error = capture(command, &policy).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // the variable is only given to the command, since tests run in
+        // parallel and share this process's environment
+        let mut shell = system_shell();
+        shell
+            .arg("echo \"$REPL_SECRET\"")
+            .env("REPL_SECRET", "hunter2");
+        environment(&mut shell, &policy);
+        let output = shell.output()?;
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "\n");
+        assert!(output.status.success());
+        Ok(())
+    }
+
+    #[test]
+    fn sessions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut session = Session::start(&Policy::default())?;
+        session.run("cd / && export GREETING=hi", true)?;
+        let output = session.run("printf \"$GREETING \"; pwd; echo oops >&2; false", true)?;
+        assert_eq!(output.stdout, "hi /");
+        assert_eq!(output.stderr, "oops");
+        assert_eq!(output.status, Some(1));
+
+        // syntax errors don't end the session, but `exit` does
+        assert_eq!(session.run("if", true)?.status, Some(2));
+        assert_eq!(session.cwd()?, "/");
+        let output = session.run("printf 'no line break'", true)?;
+        assert_eq!(output.stdout, "no line break");
+
+        // a session ends when nothing more can be framed in its output
+        for (command, ended) in [
+            ("echo bye; exit 4", "the shell session ended with status 4"),
+            ("sleep 5 & exit", "the shell session ended with status 0"),
+            ("exec >/dev/null", "the shell session ended"),
+            ("exec 1>&-", "the shell session ended"),
+        ] {
+            let mut session = Session::start(&Policy::default())?;
+            let start = Instant::now();
+            let error = session.run(command, true).unwrap_err();
+            assert_eq!(error.to_string(), ended);
+            assert!(start.elapsed() < Duration::from_secs(2));
+        }
+
+        let policy = Policy {
+            timeout: Some(Duration::from_millis(100)),
+            ..Policy::default()
+        };
+        let mut session = Session::start(&policy)?;
+        let error = session.run("sleep 5", true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        Ok(())
+    }
+}
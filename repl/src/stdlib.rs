@@ -0,0 +1,249 @@
+use crate::ast::Value;
+use crate::interpret::{ErrorKind, Interpreter, RuntimeError};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+pub struct Builtin {
+    pub name: &'static str,
+    // `None` for builtins taking any number of arguments
+    pub arity: Option<usize>,
+    function: fn(&mut Interpreter, Vec<Value>) -> Result<Value, RuntimeError>,
+}
+
+impl Builtin {
+    pub fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        if let Some(arity) = self.arity {
+            if args.len() != arity {
+                return Err(ErrorKind::Arity(self.name.to_string(), arity, args.len()).into());
+            }
+        }
+        (self.function)(interpreter, args)
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "arg",
+        arity: Some(1),
+        function: arg,
+    },
+    Builtin {
+        name: "arg_count",
+        arity: Some(0),
+        function: arg_count,
+    },
+    Builtin {
+        name: "len",
+        arity: Some(1),
+        function: len,
+    },
+    Builtin {
+        name: "lines",
+        arity: Some(1),
+        function: lines,
+    },
+    Builtin {
+        name: "print",
+        arity: None,
+        function: print,
+    },
+    Builtin {
+        name: "push",
+        arity: Some(2),
+        function: push,
+    },
+    Builtin {
+        name: "random",
+        arity: Some(2),
+        function: random,
+    },
+    Builtin {
+        name: "run",
+        arity: Some(1),
+        function: run,
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// `arg(index)` gives the script argument at `index`, counting from 0, or
+/// null past the last one.
+fn arg(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match args[..] {
+        [Value::Num(index)] if index >= 0f64 && index.fract() == 0f64 => Ok(interpreter
+            .args()
+            .get(index as usize)
+            .map_or(Value::Null, |arg| Value::Str(arg.clone()))),
+        _ => Err(ErrorKind::InvalidArguments("arg".to_string(), args).into()),
+    }
+}
+
+/// `arg_count()` gives how many arguments were passed to the script.
+fn arg_count(interpreter: &mut Interpreter, _: Vec<Value>) -> Result<Value, RuntimeError> {
+    Ok(Value::Num(interpreter.args().len() as f64))
+}
+
+/// `len(value)` gives the number of items of a list or a map, or of
+/// characters of a string.
+fn len(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let len = match &args[0] {
+        Value::Str(text) => text.chars().count(),
+        Value::List(items) => items.borrow().len(),
+        Value::Map(entries) => entries.borrow().len(),
+        _ => return Err(ErrorKind::InvalidArguments("len".to_string(), args).into()),
+    };
+    Ok(Value::Num(len as f64))
+}
+
+/// `lines(text)` splits a string, such as the output of a command, into a
+/// list of its lines.
+fn lines(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Str(text) => Ok(Value::list(
+            text.lines()
+                .map(|line| Value::Str(line.to_string()))
+                .collect(),
+        )),
+        _ => Err(ErrorKind::InvalidArguments("lines".to_string(), args).into()),
+    }
+}
+
+/// `print(values...)` prints its arguments separated by spaces. Comments
+/// are run and their output is printed.
+fn print(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut line = Vec::with_capacity(args.len());
+    for arg in args {
+        let arg = match arg {
+            Value::Comment(text) => interpreter.run_comment(&text)?,
+            arg => arg,
+        };
+        line.push(format!("{:?}", arg));
+    }
+    println!("{}", line.join(" "));
+    Ok(Value::Null)
+}
+
+/// `push(list, value)` adds a value at the end of a list.
+fn push(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match &args[..] {
+        [Value::List(items), value] => {
+            items.borrow_mut().push(value.clone());
+            Ok(Value::Null)
+        }
+        _ => Err(ErrorKind::InvalidArguments("push".to_string(), args).into()),
+    }
+}
+
+/// `random(min, max)` gives a whole number between `min` and `max`, both
+/// included. There can be at most 2^53 of them, as many whole numbers in a
+/// row as a number holds exactly.
+fn random(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    if let [Value::Num(min), Value::Num(max)] = args[..] {
+        let (min, max) = (min.ceil(), max.floor());
+        let range = max - min + 1f64;
+        if min <= max && range <= 2f64.powi(53) {
+            let seed = RandomState::new().build_hasher().finish();
+            return Ok(Value::Num(min + (seed % range as u64) as f64));
+        }
+    }
+    Err(ErrorKind::InvalidArguments("random".to_string(), args).into())
+}
+
+/// `run(comment)` runs a comment and gives its output.
+fn run(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Comment(text) => interpreter.run_comment(text),
+        value => Err(ErrorKind::NotAComment(value.clone()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::Options;
+    use crate::shell::Output;
+    use std::rc::Rc;
+
+    #[test]
+    fn random_range() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::new();
+        for _ in 0..32 {
+            let value = interpreter.call("random", vec![Value::Num(0f64), Value::Num(1f64)])?;
+            assert!(value == Value::Num(0f64) || value == Value::Num(1f64));
+        }
+
+        for max in [1e23, f64::INFINITY] {
+            assert!(matches!(
+                interpreter.call("random", vec![Value::Num(0f64), Value::Num(max)]),
+                Err(RuntimeError {
+                    kind: ErrorKind::InvalidArguments(_, _),
+                    ..
+                })
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn script_args() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::with_options(Options {
+            args: vec!["-v".to_string(), "file".to_string()],
+            ..Options::default()
+        });
+        assert_eq!(interpreter.call("arg_count", vec![])?, Value::Num(2f64));
+        assert_eq!(
+            interpreter.call("arg", vec![Value::Num(1f64)])?,
+            Value::Str("file".to_string())
+        );
+        assert_eq!(
+            interpreter.call("arg", vec![Value::Num(2f64)])?,
+            Value::Null
+        );
+        assert!(interpreter.call("arg", vec![Value::Num(0.5)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn collections() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::new();
+        let list = Value::list(vec![Value::Num(1f64)]);
+        interpreter.call("push", vec![list.clone(), Value::Null])?;
+        assert_eq!(list, Value::list(vec![Value::Num(1f64), Value::Null]));
+        assert_eq!(interpreter.call("len", vec![list])?, Value::Num(2f64));
+        assert_eq!(
+            interpreter.call("len", vec![Value::Str("héllo".to_string())])?,
+            Value::Num(5f64)
+        );
+
+        let lines = interpreter.call("lines", vec![Value::Str("a\nb\n".to_string())])?;
+        assert_eq!(
+            lines,
+            Value::list(vec![
+                Value::Str("a".to_string()),
+                Value::Str("b".to_string())
+            ])
+        );
+        assert!(interpreter.call("len", vec![Value::Num(1f64)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn run_comment() -> Result<(), Box<dyn std::error::Error>> {
+        let mut interpreter = Interpreter::new();
+        let value = interpreter.call("run", vec![Value::Comment("> echo hi".to_string())])?;
+        let output = Output {
+            stdout: "hi".to_string(),
+            stderr: String::new(),
+            status: Some(0),
+        };
+        assert_eq!(value, Value::Output(Rc::new(output)));
+        assert!(interpreter.call("run", vec![Value::Null]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,460 @@
+use crate::ast::{self, Expr, ExprKind, Stmt, StmtKind, Value};
+use std::collections::HashMap;
+
+/// The pass behind `--without-comments`: removes executable comments and
+/// every statement that uses a value derived from one.
+///
+/// Derived bindings are remembered between calls to `strip`, so a REPL
+/// session can keep feeding it one line at a time.
+pub struct Stripper {
+    // the bindings of the scopes around the code being stripped, innermost
+    // last, and whether each one's value is derived
+    scopes: Vec<HashMap<String, bool>>,
+}
+
+impl Default for Stripper {
+    fn default() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+        }
+    }
+}
+
+impl Stripper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn strip(&mut self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        statements
+            .into_iter()
+            .filter_map(|statement| self.statement(statement))
+            .collect()
+    }
+
+    fn statement(&mut self, statement: Stmt) -> Option<Stmt> {
+        let Stmt { kind, span } = statement;
+        let kind = match kind {
+            StmtKind::Comment(text) if is_executable(&text) => return None,
+            StmtKind::VariableDeclaration { name, value } => {
+                if matches!(&value, Some(value) if self.is_derived(value)) {
+                    self.declare(name, true);
+                    return None;
+                }
+                self.declare(name.clone(), false);
+                StmtKind::VariableDeclaration {
+                    name,
+                    value: value.map(|value| self.expr(value)),
+                }
+            }
+            StmtKind::ConstDeclaration { name, value } => {
+                if self.is_derived(&value) {
+                    self.declare(name, true);
+                    return None;
+                }
+                self.declare(name.clone(), false);
+                StmtKind::ConstDeclaration {
+                    name,
+                    value: self.expr(value),
+                }
+            }
+            StmtKind::CommentDeclaration { name, value } => {
+                if self.is_derived(&value) {
+                    self.declare(name, true);
+                    return None;
+                }
+                self.declare(name.clone(), false);
+                StmtKind::CommentDeclaration {
+                    name,
+                    value: self.expr(value),
+                }
+            }
+            StmtKind::FnDeclaration {
+                name,
+                params,
+                body,
+                comments,
+            } => {
+                let body = self.shadowing(&params, *body);
+                StmtKind::FnDeclaration {
+                    name,
+                    params,
+                    body: Box::new(body),
+                    comments: comments
+                        .into_iter()
+                        .filter(|comment| {
+                            !matches!(&comment.kind, StmtKind::Comment(text) if is_executable(text))
+                        })
+                        .collect(),
+                }
+            }
+            StmtKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                if self.is_derived(&condition) {
+                    return None;
+                }
+                StmtKind::If {
+                    condition: self.expr(condition),
+                    then: Box::new(self.statement(*then)?),
+                    otherwise: otherwise
+                        .and_then(|otherwise| self.statement(*otherwise).map(Box::new)),
+                }
+            }
+            StmtKind::Loop { label, body } => StmtKind::Loop {
+                label,
+                body: Box::new(self.statement(*body)?),
+            },
+            StmtKind::For {
+                name,
+                iterable,
+                body,
+            } => {
+                if self.is_derived(&iterable) {
+                    return None;
+                }
+                StmtKind::For {
+                    body: Box::new(self.shadowing(std::slice::from_ref(&name), *body)),
+                    name,
+                    iterable: self.expr(iterable),
+                }
+            }
+            StmtKind::Block(statements) => {
+                StmtKind::Block(self.scoped(&[], |stripper| stripper.strip(statements)))
+            }
+            // the value goes away, leaving the function early doesn't
+            StmtKind::Return(Some(value)) if self.is_derived(&value) => {
+                self.taint_assignments(&value);
+                StmtKind::Return(None)
+            }
+            StmtKind::Throw(value) if self.is_derived(&value) => {
+                self.taint_assignments(&value);
+                StmtKind::Throw(Expr::new(ExprKind::Literal(Value::Null), value.span))
+            }
+            StmtKind::Return(value) => StmtKind::Return(value.map(|value| self.expr(value))),
+            StmtKind::Throw(value) => StmtKind::Throw(self.expr(value)),
+            StmtKind::Try {
+                body,
+                name,
+                handler,
+            } => StmtKind::Try {
+                body: Box::new(self.statement(*body)?),
+                handler: Box::new(
+                    self.scoped(name.as_slice(), |stripper| stripper.statement(*handler))?,
+                ),
+                name,
+            },
+            StmtKind::Expr(expr) => {
+                if self.is_derived(&expr) {
+                    self.taint_assignments(&expr);
+                    return None;
+                }
+                StmtKind::Expr(self.expr(expr))
+            }
+            kind => kind,
+        };
+        Some(Stmt::new(kind, span))
+    }
+
+    // the body of a function or a loop, whose bindings shadow derived ones
+    fn shadowing(&mut self, names: &[String], body: Stmt) -> Stmt {
+        let body_span = body.span.clone();
+        self.scoped(names, |stripper| stripper.statement(body))
+            .unwrap_or_else(|| Stmt::new(StmtKind::Block(Vec::new()), body_span))
+    }
+
+    // runs `strip` in a new scope that binds `names` to values that aren't
+    // derived, and forgets what's declared in it once it's done
+    fn scoped<T>(&mut self, names: &[String], strip: impl FnOnce(&mut Self) -> T) -> T {
+        let scope = names.iter().map(|name| (name.clone(), false)).collect();
+        self.scopes.push(scope);
+        let stripped = strip(self);
+        self.scopes.pop();
+        stripped
+    }
+
+    fn declare(&mut self, name: String, derived: bool) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("the global scope is never left");
+        scope.insert(name, derived);
+    }
+
+    // a binding declared nowhere is taken to be a global one
+    fn taint(&mut self, name: &str) {
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+        {
+            Some(derived) => *derived = true,
+            None => {
+                self.scopes[0].insert(name.to_string(), true);
+            }
+        }
+    }
+
+    // strips the bodies of the anonymous functions in an expression that's kept
+    fn expr(&mut self, expr: Expr) -> Expr {
+        let Expr { kind, span, .. } = expr;
+        let kind = match kind {
+            ExprKind::Binary { left, op, right } => ExprKind::Binary {
+                left: Box::new(self.expr(*left)),
+                op,
+                right: Box::new(self.expr(*right)),
+            },
+            ExprKind::Unary { op, expr } => ExprKind::Unary {
+                op,
+                expr: Box::new(self.expr(*expr)),
+            },
+            ExprKind::Grouping(expr) => ExprKind::Grouping(Box::new(self.expr(*expr))),
+            ExprKind::Assignment(name, value) => {
+                ExprKind::Assignment(name, Box::new(self.expr(*value)))
+            }
+            ExprKind::Call { callee, args } => ExprKind::Call {
+                callee: Box::new(self.expr(*callee)),
+                args: args.into_iter().map(|arg| self.expr(arg)).collect(),
+            },
+            ExprKind::Get { object, name } => ExprKind::Get {
+                object: Box::new(self.expr(*object)),
+                name,
+            },
+            ExprKind::List(items) => {
+                ExprKind::List(items.into_iter().map(|item| self.expr(item)).collect())
+            }
+            ExprKind::Interpolation(parts) => {
+                ExprKind::Interpolation(parts.into_iter().map(|part| self.expr(part)).collect())
+            }
+            ExprKind::Map(entries) => ExprKind::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (self.expr(key), self.expr(value)))
+                    .collect(),
+            ),
+            ExprKind::Index { object, index } => ExprKind::Index {
+                object: Box::new(self.expr(*object)),
+                index: Box::new(self.expr(*index)),
+            },
+            ExprKind::IndexAssignment {
+                object,
+                index,
+                value,
+            } => ExprKind::IndexAssignment {
+                object: Box::new(self.expr(*object)),
+                index: Box::new(self.expr(*index)),
+                value: Box::new(self.expr(*value)),
+            },
+            ExprKind::Function { params, body } => ExprKind::Function {
+                body: Box::new(self.shadowing(&params, *body)),
+                params,
+            },
+            kind => kind,
+        };
+        Expr::new(kind, span)
+    }
+
+    fn is_derived(&self, expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Binary { left, right, .. } => self.is_derived(left) || self.is_derived(right),
+            ExprKind::Unary { expr, .. } | ExprKind::Grouping(expr) => self.is_derived(expr),
+            ExprKind::Literal(Value::Comment(text)) => is_executable(text),
+            ExprKind::Literal(_) => false,
+            ExprKind::Variable(name) => {
+                self.scopes.iter().rev().find_map(|scope| scope.get(name)) == Some(&true)
+            }
+            ExprKind::Assignment(_, value) => self.is_derived(value),
+            ExprKind::Call { callee, args } => {
+                self.is_derived(callee) || args.iter().any(|arg| self.is_derived(arg))
+            }
+            ExprKind::Get { object, .. } => self.is_derived(object),
+            ExprKind::List(items) | ExprKind::Interpolation(items) => {
+                items.iter().any(|item| self.is_derived(item))
+            }
+            ExprKind::Map(entries) => entries
+                .iter()
+                .any(|(key, value)| self.is_derived(key) || self.is_derived(value)),
+            ExprKind::Index { object, index } => self.is_derived(object) || self.is_derived(index),
+            ExprKind::IndexAssignment {
+                object,
+                index,
+                value,
+            } => self.is_derived(object) || self.is_derived(index) || self.is_derived(value),
+            // what the body does with derived values is stripped from it
+            ExprKind::Function { .. } => false,
+        }
+    }
+
+    // a dropped assignment leaves its target without a trustworthy value
+    fn taint_assignments(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Binary { left, right, .. } => {
+                self.taint_assignments(left);
+                self.taint_assignments(right);
+            }
+            ExprKind::Unary { expr, .. } | ExprKind::Grouping(expr) => self.taint_assignments(expr),
+            ExprKind::Assignment(name, value) => {
+                self.taint(name);
+                self.taint_assignments(value);
+            }
+            ExprKind::Call { callee, args } => {
+                self.taint_assignments(callee);
+                args.iter().for_each(|arg| self.taint_assignments(arg));
+            }
+            ExprKind::Get { object, .. } => self.taint_assignments(object),
+            ExprKind::List(items) | ExprKind::Interpolation(items) => {
+                items.iter().for_each(|item| self.taint_assignments(item))
+            }
+            ExprKind::Map(entries) => entries.iter().for_each(|(key, value)| {
+                self.taint_assignments(key);
+                self.taint_assignments(value);
+            }),
+            ExprKind::Index { object, index } => {
+                self.taint_assignments(object);
+                self.taint_assignments(index);
+            }
+            // the collection is left without a trustworthy item
+            ExprKind::IndexAssignment {
+                object,
+                index,
+                value,
+            } => {
+                if let ExprKind::Variable(name) = &object.kind {
+                    self.taint(name);
+                }
+                self.taint_assignments(object);
+                self.taint_assignments(index);
+                self.taint_assignments(value);
+            }
+            ExprKind::Literal(_) | ExprKind::Variable(_) | ExprKind::Function { .. } => {}
+        }
+    }
+}
+
+fn is_executable(comment: &str) -> bool {
+    ast::shell_command(comment).is_some() || ast::failure_command(comment).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Parser;
+
+    #[test]
+    fn executable_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        # > echo "hello, world"
+        # plain comment
+        if 5 < 3 {
+            # > sudo shutdown
+        }
+        # ! cowsay "failed"
+        fn test() {}
+        "#;
+        let expected = r#"
+        # plain comment
+        if 5 < 3 {}
+        fn test() {}
+        "#;
+
+        assert_eq!(
+            Stripper::new().strip(Parser::new(program).parse()?),
+            Parser::new(expected).parse()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn comment_values() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        comment x = # > curl google.com
+        comment note = # just a note
+        x
+        print(x)
+        print("hello")
+        "#;
+        let expected = r#"
+        comment note = # just a note
+        print("hello")
+        "#;
+
+        assert_eq!(
+            Stripper::new().strip(Parser::new(program).parse()?),
+            Parser::new(expected).parse()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn anonymous_functions() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let f = fn () {
+            # > rm -rf build
+            1
+        }
+        "#;
+        let expected = r#"
+        let f = fn () { 1 }
+        "#;
+
+        assert_eq!(
+            Stripper::new().strip(Parser::new(program).parse()?),
+            Parser::new(expected).parse()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_scopes() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let x = # > ls
+        {
+            let x = 1
+            print(x)
+        }
+        for x in [1] { print(x) }
+        print(x)
+        "#;
+        let expected = r#"
+        {
+            let x = 1
+            print(x)
+        }
+        for x in [1] { print(x) }
+        "#;
+
+        assert_eq!(
+            Stripper::new().strip(Parser::new(program).parse()?),
+            Parser::new(expected).parse()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn derived_values() -> Result<(), Box<dyn std::error::Error>> {
+        let program = r#"
+        let y = x + 1
+        let z = "hello"
+        z = y
+        if y { z }
+        fn shadow(x) { x }
+        z
+        "#;
+        let expected = r#"
+        let z = "hello"
+        fn shadow(x) { x }
+        "#;
+
+        let mut stripper = Stripper::new();
+        stripper.declare("x".to_string(), true);
+
+        assert_eq!(
+            stripper.strip(Parser::new(program).parse()?),
+            Parser::new(expected).parse()?
+        );
+        Ok(())
+    }
+}